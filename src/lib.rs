@@ -1,3 +1,5 @@
+#![recursion_limit = "256"]
+
 //! # Machine
 //!
 //! ## Features
@@ -215,7 +217,28 @@
 //! ```
 //!
 //! The `on_pass_car` method can have multiple end states, so it must
-//! return a `Traffic`.
+//! return a `Traffic`, or the `GreenPassCarOutcome` enum generated for that
+//! transition, which can only hold the declared end states:
+//!
+//! ```rust,ignore
+//! pub enum GreenPassCarOutcome {
+//!   Green(Green),
+//!   Orange(Orange),
+//! }
+//!
+//! impl Green {
+//!   pub fn on_pass_car(self, input: PassCar) -> GreenPassCarOutcome {
+//!     let count = self.count + input.count;
+//!     if count >= 10 {
+//!       GreenPassCarOutcome::Orange(Orange {})
+//!     } else {
+//!       GreenPassCarOutcome::Green(Green { count })
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! The compiler will then reject any other end state returned by the handler.
 //!
 //! The generated code will now contain a `on_pass_car` for the
 //! `Traffic` enum. Note that if a state other than `Green`
@@ -241,7 +264,7 @@
 //!
 //!   pub fn on_pass_car(self, input: PassCar) -> Traffic {
 //!     match self {
-//!       Traffic::Green(state) => state.on_pass_car(input),
+//!       Traffic::Green(state) => Traffic::from(state.on_pass_car(input)),
 //!       _ => Traffic::Error,
 //!     }
 //!   }
@@ -389,450 +412,4082 @@
 //!   }
 //! }
 //! ```
+//!
+//! ### Kinds
+//!
+//! The `machine!` macro also generates a fieldless `TrafficKind` enum, with one
+//! variant per state (and `Error`), that can be obtained with `Traffic::kind()`.
+//! It is `Copy`, `Eq` and `Hash`, so it can be matched on without borrowing the
+//! state, or used as a map key or metric label. It converts from and to the state
+//! name with `name()`, `Display` and `FromStr`:
+//!
+//! ```rust,ignore
+//! let t = Traffic::green(0);
+//! assert_eq!(t.kind(), TrafficKind::Green);
+//! assert_eq!(t.kind().name(), "Green");
+//! assert_eq!("Red".parse(), Ok(TrafficKind::Red));
+//! ```
+//!
+//! In the same way, `transitions!` generates a `TrafficMessageKind` enum, returned
+//! by `TrafficMessages::kind()`.
+//! 
+//! A state cannot be named `Kind`, since its constructor would collide with
+//! `kind()`.
+//!
+//! ### Queries
+//!
+//! `transitions!` also generates methods to inspect the transition table from
+//! the current state, without executing a transition:
+//!
+//! - `can_handle(&self, &TrafficMessages) -> bool` returns true if the current state accepts the message
+//! - `accepted_messages(&self) -> &'static [TrafficMessageKind]` lists the messages accepted by the current state
+//! - `next_state_kind(&self, &TrafficMessages) -> Option<TrafficKind>` returns the end state of a
+//!   single end transition, `None` for transitions with multiple end states, and `Some(TrafficKind::Error)`
+//!   if the message is not accepted
+//!
+//! ```rust,ignore
+//! let t = Traffic::orange();
+//! assert!(t.can_handle(&TrafficMessages::Advance(Advance)));
+//! assert_eq!(t.accepted_messages(), &[TrafficMessageKind::Advance]);
+//! assert_eq!(t.next_state_kind(&TrafficMessages::Advance(Advance)), Some(TrafficKind::Red));
+//! ```
+//!
+//! ### Observers
+//!
+//! `transitions!` generates a `TrafficObserver` trait, with default empty methods
+//! called on each transition and on each rejected message (a message not accepted
+//! by the current state, that moves the machine to the `Error` state). Messages
+//! passed to `Traffic::execute_with` will notify the observer, with a copy of the
+//! message taken before the handler consumes it. With the `by_ref` option, the
+//! borrowed message is passed instead. Without `Clone` in the `derive(..)`
+//! option, `execute_with` and the observer trait are not generated:
+//!
+//! ```rust,ignore
+//! struct Audit;
+//!
+//! impl TrafficObserver for Audit {
+//!   fn on_transition(&mut self, from: TrafficKind, message: &TrafficMessages, to: TrafficKind) {
+//!     println!("{} -> {} on {:?}", from, to, message);
+//!   }
+//!
+//!   fn on_rejected(&mut self, from: TrafficKind, message: &TrafficMessages) {
+//!     println!("{} rejected {}", from, message.kind());
+//!   }
+//! }
+//!
+//! let t = Traffic::green(0).execute_with(&mut Audit, TrafficMessages::Advance(Advance));
+//! ```
+//!
+//! ### Runtime logging
+//!
+//! With the `runtime-log` cargo feature, the code generated by `transitions!`
+//! emits records through the [log](https://crates.io/crates/log) crate, which
+//! must then be a dependency of the crate using the macros:
+//!
+//! - `execute` emits a trace record with the current state and the received message
+//! - each `on_*` method emits a debug record with the start and end states, like `Traffic: Green -> Orange on Advance`
+//! - a message not accepted by the current state emits a warning, like `Traffic: Red rejected PassCar, moving to Error`
+//!
+//! ```toml
+//! [dependencies]
+//! log = "^0.4"
+//! machine = { version = "^0.3", features = ["runtime-log"] }
+//! ```
+//!
+//! ### Metrics
+//!
+//! With the `metrics` option, `transitions!` generates a `TrafficInstrumented`
+//! wrapper around the machine, reporting to a `TrafficMetricsSink` every
+//! transition, every rejected message, and the time spent in a state when it is
+//! left. Time is read from a `TrafficClock`, implemented for any
+//! `Fn() -> Instant`, so tests can control it:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic, metrics,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Orange, Advance) => Red,
+//!     (Red, Advance) => Green
+//!   ]
+//! );
+//!
+//! struct Prometheus;
+//!
+//! impl TrafficMetricsSink for Prometheus {
+//!   fn transition(&mut self, from: TrafficKind, message: TrafficMessageKind, to: TrafficKind) {
+//!     // increment a counter labeled with from, message and to
+//!   }
+//!
+//!   fn dwell(&mut self, state: TrafficKind, duration: Duration) {
+//!     // record duration in a histogram labeled with state
+//!   }
+//! }
+//!
+//! let mut t = TrafficInstrumented::new(Traffic::green(0), Prometheus, Instant::now);
+//! t.execute(TrafficMessages::Advance(Advance));
+//! assert_eq!(t.machine(), &Traffic::orange());
+//! ```
+//!
+//! ### Timeouts
+//!
+//! A row can fire after the machine spent some time in a state, with a duration
+//! like `500ms`, `5s`, `2m`, `1h`, or any expression returning a `Duration`.
+//! The state then implements `on_timeout(self)` returning the end state, or an
+//! outcome enum named after the state (`ConnectingTimeoutOutcome`) if there are
+//! multiple end states:
+//!
+//! ```rust,ignore
+//! transitions!(Connection,
+//!   [
+//!     (Connecting, Established) => Connected,
+//!     (Connecting, after(5s)) => Failed,
+//!     (Connected, after(Duration::from_secs(30))) => Idle
+//!   ]
+//! );
+//!
+//! impl Connecting {
+//!   pub fn on_timeout(self) -> Failed {
+//!     Failed {}
+//!   }
+//! }
+//! ```
+//!
+//! The machine gets `timeout()`, returning the duration of its current state, and
+//! `on_timeout()`. The `ConnectionTimers` wrapper remembers when the current state
+//! was entered, using a `ConnectionClock` (implemented for any `Fn() -> Instant`
+//! and for `ConnectionManualClock`, which only moves when told to):
+//!
+//! ```rust,ignore
+//! let clock = ConnectionManualClock::new(Instant::now());
+//! let mut c = ConnectionTimers::new(Connection::connecting(), clock.clone());
+//!
+//! clock.advance(Duration::from_secs(5));
+//! assert!(c.poll_timeouts(clock.now()));
+//! assert_eq!(c.machine(), &Connection::failed());
+//! ```
+//!
+//! A message accepted by the current state restarts its timer. When several
+//! timeouts expired, `poll_timeouts` fires them in order, each starting from the
+//! previous deadline.
+//!
+//! ### Runner
+//!
+//! With the `runner` option, every handler receives a `&mut TrafficQueue` as
+//! last argument, to raise follow-up messages. The machine's `on_*` methods and
+//! `execute` take it too:
+//!
+//! ```rust,ignore
+//! transitions!(Request, runner,
+//!   [
+//!     (Headers, HeaderEnd) => Ready,
+//!     (Ready, StartBody) => Body
+//!   ]
+//! );
+//!
+//! impl Headers {
+//!   pub fn on_header_end(self, _: HeaderEnd, queue: &mut RequestQueue) -> Ready {
+//!     queue.push(RequestMessages::StartBody(StartBody));
+//!     Ready {}
+//!   }
+//! }
+//! ```
+//!
+//! The generated `RequestRunner` owns the machine and the queue. `send` processes
+//! a message, then the raised messages in FIFO order, each to completion before
+//! the next one. It stops at the first message moving the machine to the error
+//! state, drops the remaining messages and returns a `RequestRunError` describing
+//! it:
+//!
+//! ```rust,ignore
+//! let mut runner = RequestRunner::new(Request::headers());
+//! runner.send(RequestMessages::HeaderEnd(HeaderEnd))?;
+//! assert_eq!(runner.machine(), &Request::body());
+//! ```
+//!
+//! ### Deferred messages
+//!
+//! With the `runner` option, a state can defer a message it cannot handle yet,
+//! instead of moving to the error state:
+//!
+//! ```rust,ignore
+//! transitions!(Stream, runner,
+//!   [
+//!     (Handshaking, Handshake) => Open,
+//!     (Handshaking, Data) => defer,
+//!     (Open, Data) => Open
+//!   ]
+//! );
+//! ```
+//!
+//! The machine stays in its state and the message is kept in the queue. The
+//! runner replays deferred messages, in arrival order and before the raised ones,
+//! as soon as the machine reaches a state accepting them. The `defers` query
+//! tells if the current state defers a message.
+//!
+//! A deferred message is dropped when the machine reaches a state that neither
+//! accepts nor defers it. The runner keeps the dropped messages until they are
+//! taken with `take_dropped()`, and with the `runtime-log` feature each one is
+//! logged as a warning.
+//!
+//! ### Output commands
+//!
+//! To keep the handlers pure, they can emit commands for the caller to perform,
+//! with the `output` option giving their type. Handlers receive a
+//! `&mut Vec<Command>` after the message (before the queue with the `runner`
+//! option), and the machine's `on_*` methods and `execute` return the next state
+//! with the emitted commands:
+//!
+//! ```rust,ignore
+//! transitions!(Link, output = Command,
+//!   [
+//!     (Idle, Send) => Waiting
+//!   ]
+//! );
+//!
+//! impl Idle {
+//!   pub fn on_send(self, input: Send, output: &mut Vec<Command>) -> Waiting {
+//!     output.push(Command::Transmit(input.seq));
+//!     output.push(Command::StartTimer);
+//!     Waiting { seq: input.seq }
+//!   }
+//! }
+//!
+//! let (link, commands) = Link::idle().execute(LinkMessages::Send(Send { seq: 1 }));
+//! ```
+//!
+//! The `execute` methods of the wrappers return the commands too, as does
+//! `poll_timeouts` along with its boolean, and the runner
+//! accumulates them until `take_output` is called.
+//!
+//! ### Context
+//!
+//! Data common to all states can be declared once, as a context living beside
+//! the current state, instead of being copied in every state struct:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[machine(context = Exchange)]
+//!   enum Http {
+//!     Initial,
+//!     Headers,
+//!     Done { length: usize },
+//!   }
+//! );
+//! ```
+//!
+//! This generates the `HttpContext` alias and an `HttpMachine` holding the state
+//! and the context, with `new(state, context)`, `state()`, `context()`,
+//! `context_mut()` and `into_parts()`. With the `context` option, or `inherit`,
+//! `transitions!` passes the context to every handler, as first argument after
+//! the message. The machine's methods take it as argument, and `HttpMachine` gets
+//! an `execute` method passing its own context:
+//!
+//! ```rust,ignore
+//! transitions!(Http, context,
+//!   [
+//!     (Initial, RequestLine) => Headers,
+//!     (Headers, Header) => Headers,
+//!     (Headers, HeaderEnd) => Done
+//!   ]
+//! );
+//!
+//! impl Headers {
+//!   pub fn on_header(self, input: Header, context: &mut HttpContext) -> Headers {
+//!     context.headers.push(input.0);
+//!     Headers {}
+//!   }
+//! }
+//!
+//! let mut http = HttpMachine::new(Http::initial(), Exchange::default());
+//! http.execute(HttpMessages::RequestLine(RequestLine("GET / HTTP/1.1".to_string())));
+//! ```
+//!
+//! ### Borrowed messages
+//!
+//! With the `by_ref` option, messages are passed by reference: handlers and the
+//! machine's `on_*` methods take `&Msg`, and `execute` takes `&SubscriberMessages`,
+//! so the same message can be sent to several machines without cloning it:
+//!
+//! ```rust,ignore
+//! transitions!(Subscriber, by_ref,
+//!   [
+//!     (Waiting, Publish<'a>) => Received,
+//!     (Received, Publish<'a>) => Received
+//!   ]
+//! );
+//!
+//! impl Waiting {
+//!   pub fn on_publish(self, input: &Publish) -> Received {
+//!     Received { total: input.payload.len() }
+//!   }
+//! }
+//!
+//! let message = SubscriberMessages::Publish(Publish { payload: &payload });
+//! let subscribers = subscribers.into_iter().map(|s| s.execute(&message)).collect::<Vec<_>>();
+//! ```
+//!
+//! The wrappers' `execute` methods take a reference too. The runner still takes
+//! ownership of the messages it queues, and deferred messages are cloned into the
+//! queue.
+//!
+//! ### Inline handlers
+//!
+//! Trivial handlers can be written in the row, as a closure after the end states.
+//! `transitions!` generates the `on_*` method from it, binding the closure
+//! parameters to the state and the message:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic,
+//!   [
+//!     (Green, Advance) => Orange || Orange {},
+//!     (Orange, Advance) => Red |_, _| Red {},
+//!     (Red, Advance) => Green,
+//!     (Green, PassCar) => [Green, Orange] |s, m: PassCar| {
+//!       let count = s.count + m.count;
+//!       if count >= 10 {
+//!         GreenPassCarOutcome::Orange(Orange {})
+//!       } else {
+//!         GreenPassCarOutcome::Green(Green { count })
+//!       }
+//!     },
+//!     (Red, after(30s)) => Green |_| Green { count: 0 }
+//!   ]
+//! );
+//! ```
+//!
+//! Rows without a closure still need their handler to be implemented. Rows with
+//! multiple end states return the outcome enum, and timeout closures only take
+//! the state. The parameters after the message, if any, are bound to the
+//! arguments added by the `context`, `output` and `runner` options, in that order.
+//! Unused closure parameters are reported like any unused variable, and the
+//! closure cannot be `move` nor declare a return type, which comes from the row.
+//!
+//! ### Handler traits
+//!
+//! With the `traits` option, `transitions!` declares the handlers of each state
+//! in a trait named after it, that the state implements:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic, traits,
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, PassCar) => [Green, Orange],
+//!     (Red, after(30s)) => Green
+//!   ]
+//! );
+//!
+//! impl GreenTransitions for Green {
+//!   fn on_advance(self, _: Advance) -> Orange {
+//!     Orange {}
+//!   }
+//!
+//!   fn on_pass_car(self, input: PassCar) -> GreenPassCarOutcome {
+//!     // ...
+//!   }
+//! }
+//!
+//! impl RedTransitions for Red {
+//!   fn on_timeout(self) -> Green {
+//!     Green { count: 0 }
+//!   }
+//! }
+//! ```
+//!
+//! A missing handler is then reported with the trait it belongs to, instead of
+//! the `E0599` error above:
+//!
+//! ```text,ignore
+//! error[E0046]: not all trait items implemented, missing: `on_timeout`
+//!   --> tests/traits.rs:46:1
+//!    |
+//! ...
+//! 46 |   impl RedTransitions for Red {
+//!    |   ^^^^^^^^^^^^^^^^^^^^^^^^^^^ missing `on_timeout` in implementation
+//! ```
+//!
+//! Inline handlers are not part of the traits.
+//!
+//! ### Messages
+//!
+//! Messages can be declared in `transitions!` instead of being defined beforehand,
+//! with the syntax of enum variants:
+//!
+//! ```rust,ignore
+//! transitions!(Traffic,
+//!   messages {
+//!     Advance,
+//!     /// a car passed the light
+//!     PassCar { count: u8 },
+//!     Reset(u8),
+//!   },
+//!   [
+//!     (Green, Advance) => Orange,
+//!     (Green, PassCar) => [Green, Orange],
+//!     (Red, Reset) => Green
+//!   ]
+//! );
+//! ```
+//!
+//! This generates a struct deriving `Clone`, `Debug` and `PartialEq` for each of
+//! them, keeping their attributes, and a `From` implementation converting them to
+//! `TrafficMessages`:
+//!
+//! ```rust,ignore
+//! let t = Traffic::green(0).execute(PassCar { count: 5 }.into());
+//! ```
+//!
+//! Declared messages must be used in a transition, and can be mixed with messages
+//! defined outside of the macro.
+//!
+//! ## Message names
+//!
+//! Each message is named after the last segment of its type's path. That name is
+//! used for the `<Name>Messages` variant and for the `on_*` method. When two
+//! messages end with the same segment, or when the message type is not a path
+//! (tuples, references, slices), give it a name with `as`:
+//!
+//! ```rust,ignore
+//! transitions!(Player,
+//!   [
+//!     (Stopped, local::Start as StartLocal) => Playing,
+//!     (Stopped, remote::Start as StartRemote) => Playing,
+//!     (Playing, (u8, u8) as Seek) => Seeking,
+//!     (Seeking, &'a [u8] as Bytes) => Playing
+//!   ]
+//! );
+//!
+//! impl Seeking {
+//!   pub fn on_bytes<'a>(self, input: &'a [u8]) -> Playing {
+//!     Playing { track: self.position.0 + input.len() as u8 }
+//!   }
+//! }
+//! ```
+//!
+//! Lifetimes borrowed by a message become parameters of the messages enum. A
+//! message type without a name, or a name used by two different types, is
+//! reported as a compile error.
+//!
+//! The messages enum derives `Clone`, `Debug` and `PartialEq`, which every
+//! message type must then implement. The `derive(..)` option replaces that list,
+//! for messages like trait objects that cannot implement them. The runner's queue
+//! still needs `Debug`:
+//!
+//! ```rust,ignore
+//! transitions!(Door, derive(Debug),
+//!   [
+//!     (Closed, Box<dyn Event> as Boxed) => Open
+//!   ]
+//! );
+//! ```
+//!
+//! ## Transition groups
+//!
+//! The transitions of a machine can be split across several `transitions!`
+//! calls, for example to keep them next to their handlers or behind a feature.
+//! Each call with the `group = name` option only declares its rows. Then
+//! `transitions_finish!` merges the listed groups and takes the options:
+//!
+//! ```rust,ignore
+//! transitions!(Lamp, group = basic,
+//!   [
+//!     (Off, Switch) => On,
+//!     (On, Switch) => Off
+//!   ]
+//! );
+//!
+//! #[cfg(feature = "dimming")]
+//! #[macro_use]
+//! mod dimming {
+//!   transitions!(Lamp, group = dim,
+//!     [
+//!       (On, Dim) => Dimmed,
+//!       (Dimmed, Switch) => Off
+//!     ]
+//!   );
+//! }
+//!
+//! transitions_finish!(Lamp, metrics, [basic, #[cfg(feature = "dimming")] dim]);
+//! ```
+//!
+//! A group defines a `macro_rules!` macro named `<name>_transitions_<group>`, so
+//! it must appear before `transitions_finish!`. A group declared in a module
+//! needs `#[macro_use]` on that module. The message types are resolved where
+//! `transitions_finish!` is called. A group listed with a `#[cfg(..)]` attribute
+//! is only merged when that configuration is enabled.
+//!
+//! ## Variant and field attributes
+//!
+//! The attributes of a variant are copied to the generated struct, after the
+//! attributes of the enum. Field attributes are kept on the struct fields:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Upload {
+//!     /// waiting for the first chunk
+//!     #[derive(Default)]
+//!     Pending,
+//!     Sending {
+//!       /// bytes sent so far
+//!       sent: usize,
+//!       #[cfg(feature = "checksum")]
+//!       checksum: u32,
+//!     },
+//!     #[cfg(feature = "resume")]
+//!     Paused { sent: usize },
+//!     Done,
+//!   }
+//! );
+//! ```
+//!
+//! `#[doc]` and `#[cfg]` also apply to the enum variant. `#[cfg]` applies to
+//! everything generated for that state, like its constructor and its kind. A
+//! field removed by `#[cfg]` is also removed from the constructor's arguments.
+//!
+//! ## Tuple and unit states
+//!
+//! Tuple variants generate tuple structs, and their constructor takes the fields
+//! in order. Unit variants generate unit structs:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   enum Job {
+//!     Queued,
+//!     Running { worker: u8 },
+//!     Retrying(u32, Duration),
+//!   }
+//! );
+//!
+//! assert_eq!(Job::retrying(2, Duration::from_secs(4)), Job::Retrying(Retrying(2, Duration::from_secs(4))));
+//! assert_eq!(Job::queued(), Job::Queued(Queued));
+//! ```
+//!
+//! In `methods!`, a positional field is given a name with `as`:
+//!
+//! ```rust,ignore
+//! methods!(Job,
+//!   [
+//!     Retrying => get 0 as attempts: u32,
+//!     Retrying => set 1 as delay: Duration
+//!   ]
+//! );
+//! ```
+//!
+//! ## Visibility
+//!
+//! The visibility written on the `machine!` enum applies to the generated enum,
+//! structs, constructors and kinds. The items generated by `transitions!`,
+//! `transitions_finish!` and `methods!` are `pub` unless a visibility is written
+//! before the machine name, or the `inherit` option takes the machine's one:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   pub(crate) enum Handshake {
+//!     Waiting,
+//!     Authenticated { token: Token },
+//!   }
+//! );
+//!
+//! transitions!(Handshake, inherit,
+//!   [
+//!     (Waiting, Login) => Authenticated
+//!   ]
+//! );
+//!
+//! methods!(Handshake, inherit,
+//!   [
+//!     Authenticated => get token: Token,
+//!     Authenticated => pub(self) set token: Token
+//!   ]
+//! );
+//! ```
+//!
+//! Without a visibility on the enum, the generated items are `pub`. Use
+//! `pub(self)` to keep them private to the module.
+//!
+//! `machine!` passes its visibility, error state, namespace and context on
+//! through a macro named after the machine (`handshake_machine_defaults!`), so
+//! with `inherit`, `transitions!` and `methods!` must come after `machine!`, in
+//! the same module or in a child one, and be called by their name rather than by
+//! a path. A visibility written before the machine name still overrides the
+//! machine's one, and in `methods!`, a visibility written after `=>` overrides it
+//! for that entry. The other written options must match the machine's settings.
+//! Without `inherit`, they are checked against hidden constants of the machine.
+//!
+//! ## Namespaced states
+//!
+//! Two machines in the same module cannot both have an `Idle` state, since each
+//! generates a `pub struct Idle`. With `#[machine(namespace)]`, the state structs
+//! are defined in a module named after the machine. `transitions!` and `methods!`
+//! take the `namespace` option, or `inherit`, to find them there:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   #[machine(namespace)]
+//!   enum Pump {
+//!     Idle,
+//!     Running(u8),
+//!   }
+//! );
+//!
+//! transitions!(Pump, namespace,
+//!   [
+//!     (Idle, Start) => Running,
+//!     (Running, Stop) => Idle
+//!   ]
+//! );
+//!
+//! methods!(Pump, namespace,
+//!   [
+//!     Running => get 0 as speed: u8
+//!   ]
+//! );
+//!
+//! impl pump::Idle {
+//!   pub fn on_start(self, _: Start) -> pump::Running {
+//!     pump::Running(3)
+//!   }
+//! }
+//! ```
+//!
+//! The module imports everything from its parent. Private fields stay visible
+//! to the module declaring the machine. The items named after a state, like
+//! outcome enums and handler traits, are defined next to the machine and prefixed
+//! with its name, as in `PumpIdleStartOutcome`, `PumpIdleTransitions` or
+//! `PumpIdleTimeoutOutcome`.
+//!
+//! ## Error state
+//!
+//! The error state added by `machine!` is named `Error` by default, with an
+//! `error()` constructor. When the machine already has an `Error` state, give the
+//! generated one another name with `#[machine(error = Invalid)]`. Then pass the
+//! same option to `transitions!`, or take it from the machine with `inherit`:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   #[derive(Clone,Debug,PartialEq)]
+//!   #[machine(error = Invalid)]
+//!   enum Compile {
+//!     Parsing,
+//!     Error { line: u32 },
+//!     Done,
+//!   }
+//! );
+//!
+//! transitions!(Compile, error = Invalid,
+//!   [
+//!     (Parsing, Fail) => Error,
+//!     (Parsing, Finish) => Done
+//!   ]
+//! );
+//!
+//! assert_eq!(Compile::done().execute(CompileMessages::Finish(Finish)), Compile::invalid());
+//! ```
+//!
+//! With `#[machine(no_error)]`, and the `no_error` option of `transitions!` or
+//! `inherit`, the machine has no error state. Every state must then handle every
+//! message, and a missing transition is reported as a non-exhaustive match.
+//! `on_timeout` leaves states without a timeout as they are, and the runner never
+//! fails.
+//!
+//! With `inherit`, the written options must match the machine's ones. Without it,
+//! `transitions!` checks its error state against a hidden constant of the
+//! machine, and a mismatch fails to compile on a missing constant such as
+//! `Compile::__machine_error_state_is_Error`.
+//!
+//! ## Recovering from the error state
+//!
+//! Rows can start from the error state, to recover a machine without rebuilding
+//! it. The error state has no struct of its own, so its handlers are implemented
+//! on the `<Name>ErrorState` marker struct that `transitions!` generates when a
+//! row mentions the error state:
+//!
+//! ```rust,ignore
+//! transitions!(Worker,
+//!   [
+//!     (Busy, Job) => [Busy, Error],
+//!     (Error, Reset) => [Initial, Error],
+//!     (Error, after(10s)) => Initial
+//!   ]
+//! );
+//!
+//! impl WorkerErrorState {
+//!   pub fn on_reset(self, input: Reset) -> ErrorResetOutcome {
+//!     if input.force {
+//!       ErrorResetOutcome::Initial(Initial {})
+//!     } else {
+//!       ErrorResetOutcome::Error(self)
+//!     }
+//!   }
+//!
+//!   pub fn on_timeout(self) -> Initial {
+//!     Initial {}
+//!   }
+//! }
+//! ```
+//!
+//! Like other handlers, they also receive the context, the output and the queue
+//! when those options are enabled. Messages without a recovery row keep the
+//! machine in the error state. With `#[machine(error = Invalid)]`, the marker is
+//! named `<Name>InvalidState`.
+//!
+//! ## Initial and final states
+//!
+//! One variant can be marked `#[initial]`, and any number of them `#[final]`:
+//!
+//! ```rust,ignore
+//! machine!(
+//!   enum Download {
+//!     #[initial]
+//!     Queued { retries: u8, url: String },
+//!     Running(u64),
+//!     #[final]
+//!     Complete,
+//!   }
+//! );
+//! ```
+//!
+//! The initial state gives the machine an `initial()` constructor and a
+//! `Default` implementation, building the state with the default value of each
+//! field. They follow the `#[cfg]` attributes of the variant. A state named
+//! `Initial` already has an `initial()` constructor, so it must be the one marked
+//! `#[initial]` when the machine has it.
+//!
+//! `is_final()` tells whether the machine reached a final state, and
+//! `is_error()` whether it is in the error state (`is_invalid()` with
+//! `#[machine(error = Invalid)]`).
+//!
+//! A final state cannot be left: `transitions!` fails to compile when a row or a
+//! timeout goes from a final state to another state, because final states do not
+//! implement the `<Name>NonFinal` trait. Rows staying in the same state are
+//! accepted. The DOT file draws an arrow to the initial state, and final states
+//! as double circles.
+//!
+//! ## Inspecting the state
+//!
+//! `machine!` generates helpers for each state, named after the variant:
+//!
+//! ```rust,ignore
+//! let mut traffic = Traffic::green(2);
+//! assert!(traffic.is_green());
+//!
+//! if let Some(green) = traffic.as_green_mut() {
+//!   green.count += 1;
+//! }
+//! assert_eq!(traffic.as_green(), Some(&Green { count: 3 }));
+//!
+//! // gives the machine back when it is in another state
+//! let green: Result<Green, Traffic> = traffic.into_green();
+//! ```
+//!
+//! The state structs also convert to and from the machine, with `From<Green>
+//! for Traffic` and `TryFrom<Traffic> for Green`, whose error is the machine
+//! itself:
+//!
+//! ```rust,ignore
+//! let traffic: Traffic = Green { count: 1 }.into();
+//! let green = Green::try_from(traffic)?;
+//! ```
+
+extern crate case;
+extern crate proc_macro;
+/*
+#[macro_use] mod dynamic_machine;
+
+#[macro_export]
+macro_rules! machine(
+  ( $($token:tt)* ) => ( static_machine!( $($token)* ); );
+);
+*/
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions, create_dir};
+use std::io::{Seek, Write};
+
+use case::CaseExt;
+use syn::export::{Span, TokenStream2};
+use syn::parse::{Parse, ParseStream, Parser, Result};
+use syn::spanned::Spanned;
+use syn::{
+    Abi, Attribute, Expr, ExprClosure, Fields, FnArg, FnDecl, Generics, Ident, Index, ItemEnum, Member, MethodSig, Path, ReturnType, Type,
+    WhereClause, PathArguments, GenericArgument, Lifetime, Meta, NestedMeta, TypeParamBound, Variant, Visibility,
+};
+use quote::ToTokens;
+
+struct Machine {
+    attributes: Vec<Attribute>,
+    options: MachineOptions,
+    data: ItemEnum,
+    /// the variant marked `#[initial]`
+    initial: Option<Ident>,
+    /// the variants marked `#[final]`
+    finals: Vec<Ident>,
+}
+
+/// `final` is a keyword, which syn rejects as an attribute name, so `#[final]`
+/// is renamed before parsing
+const FINAL_ATTRIBUTE: &str = "machine_final";
+
+fn rename_final_attributes(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    use proc_macro::{Delimiter, Group, TokenTree};
+
+    input
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Group(ref group) => {
+                let stream = if group.delimiter() == Delimiter::Bracket && group.stream().to_string() == "final" {
+                    TokenTree::Ident(proc_macro::Ident::new(FINAL_ATTRIBUTE, group.span())).into()
+                } else {
+                    rename_final_attributes(group.stream())
+                };
+                let mut renamed = Group::new(group.delimiter(), stream);
+                renamed.set_span(group.span());
+                TokenTree::Group(renamed)
+            }
+            tt => tt,
+        })
+        .collect()
+}
+
+impl Parse for Machine {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut attributes: Vec<Attribute> = Vec::new();
+        let mut options = MachineOptions::default();
+        for attribute in input.call(Attribute::parse_outer)? {
+            if attribute.path.is_ident("machine") {
+                (|input: ParseStream| options.parse_attribute(input)).parse2(attribute.tts)?;
+            } else {
+                attributes.push(attribute);
+            }
+        }
+        let mut data: syn::ItemEnum = input.parse()?;
+
+        // the state markers are removed from the attributes copied to the structs
+        let mut initial: Option<Ident> = None;
+        let mut finals = Vec::new();
+        for variant in data.variants.iter_mut() {
+            let mut attrs = Vec::new();
+            for attr in variant.attrs.drain(..) {
+                if attr.path.is_ident("initial") {
+                    if let Some(ref initial) = initial {
+                        return Err(syn::Error::new_spanned(
+                            attr,
+                            format!("`{}` is already the initial state", initial),
+                        ));
+                    }
+                    initial = Some(variant.ident.clone());
+                } else if attr.path.is_ident(FINAL_ATTRIBUTE) {
+                    finals.push(variant.ident.clone());
+                } else {
+                    attrs.push(attr);
+                }
+            }
+            variant.attrs = attrs;
+        }
+
+        // the constructor of a state named `Kind` would be the `kind()` method
+        if let Some(variant) = data.variants.iter().find(|v| v.ident == "Kind") {
+            return Err(syn::Error::new(
+                variant.ident.span(),
+                "a state named `Kind` conflicts with the generated `kind()` method, rename it",
+            ));
+        }
+
+        // a state named `Initial` has its own `initial()` constructor
+        if let Some(ref initial) = initial {
+            if let Some(variant) = data.variants.iter().find(|v| v.ident == "Initial" && v.ident != *initial) {
+                return Err(syn::Error::new(
+                    variant.ident.span(),
+                    format!("`{}` is the initial state, mark `Initial` with `#[initial]` or rename it", initial),
+                ));
+            }
+        }
+
+        if let Some(ref error) = options.error {
+            if options.no_error {
+                return Err(syn::Error::new(error.span(), "`error` cannot be combined with `no_error`"));
+            }
+        }
+        if let Some(error) = options.error_ident() {
+            if let Some(variant) = data.variants.iter().find(|v| v.ident == error) {
+                return Err(syn::Error::new(
+                    variant.ident.span(),
+                    format!("`{}` is the error state, rename it with `#[machine(error = ..)]`", error),
+                ));
+            }
+        }
+
+        Ok(Machine {
+            attributes,
+            options,
+            data,
+            initial,
+            finals,
+        })
+    }
+}
+
+/// options given to `machine!` with `#[machine(..)]` attributes
+#[derive(Debug, Default)]
+struct MachineOptions {
+    /// data shared by all states, stored next to the state in `<Name>Machine`
+    pub context: Option<Type>,
+    /// define the state structs in a module named after the machine
+    pub namespace: bool,
+    /// the name of the error state, `Error` by default
+    pub error: Option<Ident>,
+    /// do not generate an error state
+    pub no_error: bool,
+}
+
+impl MachineOptions {
+    /// the error state, unless the machine opted out of it
+    fn error_ident(&self) -> Option<Ident> {
+        error_ident(&self.error, self.no_error)
+    }
+
+    fn parse_attribute(&mut self, input: ParseStream) -> Result<()> {
+        let content;
+        parenthesized!(content in input);
+
+        while !content.is_empty() {
+            let name: Ident = content.parse()?;
+            match name.to_string().as_str() {
+                "context" => {
+                    let _: Token![=] = content.parse()?;
+                    self.context = Some(content.parse()?);
+                }
+                "namespace" => self.namespace = true,
+                "error" => {
+                    let _: Token![=] = content.parse()?;
+                    self.error = Some(content.parse()?);
+                }
+                "no_error" => self.no_error = true,
+                _ => return Err(syn::Error::new(name.span(), format!("unknown option `{}`", name))),
+            }
+
+            if !content.is_empty() {
+                let _: Token![,] = content.parse()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[proc_macro]
+pub fn machine(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let input = rename_final_attributes(input);
+    let ast = parse_macro_input!(input as Machine);
+
+    // Build the impl
+    let (name, gen) = impl_machine(&ast);
+
+    trace!("generated: {}", gen);
+
+    let file_name = format!("target/machine/{}.rs", name.to_string().to_lowercase());
+    let _ = create_dir("target/machine");
+    File::create(&file_name)
+        .and_then(|mut file| {
+            file.seek(std::io::SeekFrom::End(0))?;
+            file.write_all(gen.to_string().as_bytes())?;
+            file.flush()
+        })
+        .expect("error writing machine definition");
+
+    gen
+}
+
+fn impl_machine(m: &Machine) -> (&Ident, syn::export::TokenStream) {
+    let Machine { attributes, options, data, initial, finals } = m;
+    let ast = data;
+    //println!("attributes: {:?}", attributes);
+    //println!("ast: {:#?}", ast);
+
+    let machine_name = &ast.ident;
+    let vis = &item_vis(&ast.vis);
+    let namespace = namespace_ident(machine_name, options.namespace);
+    let variants_names = &ast.variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
+    let structs_names = &variants_names.iter().map(|v| state_path(&namespace, v)).collect::<Vec<_>>();
+    // a state's cfg applies to everything generated for it
+    let cfgs = &ast.variants.iter().map(|v| cfg_attributes(&v.attrs)).collect::<Vec<_>>();
+    let variants_attrs = ast
+        .variants
+        .iter()
+        .map(|v| {
+            v.attrs
+                .iter()
+                .filter(|attr| attr.path.is_ident("cfg") || attr.path.is_ident("doc"))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let error = options.error_ident();
+    let error_variant = error.iter();
+
+    // define the state enum
+    let toks = quote! {
+      #(#attributes)*
+      #vis enum #machine_name {
+        #(#error_variant,)*
+        #(#(#variants_attrs)* #variants_names(#structs_names)),*
+      }
+    };
+
+    let mut stream = proc_macro::TokenStream::from(toks);
+    stream.extend(proc_macro::TokenStream::from(impl_defaults(vis, machine_name, options)));
+
+    // define structs for each state, with the attributes of the variant
+    let mut structs = TokenStream2::new();
+    for ref variant in ast.variants.iter() {
+        let name = &variant.ident;
+        let variant_attributes = &variant.attrs;
+
+        let fields = &variant
+            .fields
+            .iter()
+            .map(|f| {
+                let attrs = &f.attrs;
+                let ty = &f.ty;
+                // private fields stay visible to the module declaring the machine
+                let vis = match f.vis {
+                    Visibility::Inherited if namespace.is_some() => quote! { pub(super) },
+                    ref vis => quote! { #vis },
+                };
+
+                match f.ident {
+                    Some(ref ident) => quote! {
+                      #(#attrs)*
+                      #vis #ident: #ty
+                    },
+                    None => quote! {
+                      #(#attrs)*
+                      #vis #ty
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let body = match variant.fields {
+            Fields::Named(_) => quote! { { #(#fields),* } },
+            Fields::Unnamed(_) => quote! { ( #(#fields),* ); },
+            Fields::Unit => quote! { ; },
+        };
+
+        // the namespace module carries the visibility
+        let struct_vis = if namespace.is_some() { quote! { pub } } else { vis.clone() };
+        structs.extend(quote! {
+          #(#attributes)*
+          #(#variant_attributes)*
+          #struct_vis struct #name #body
+        });
+    }
+
+    let toks = match namespace {
+        Some(ref namespace) => quote! {
+          #vis mod #namespace {
+            use super::*;
+
+            #structs
+          }
+        },
+        None => structs,
+    };
+    stream.extend(proc_macro::TokenStream::from(toks));
+
+    let methods = &ast
+        .variants
+        .iter()
+        .map(|variant| {
+            let fn_name = Ident::new(&variant.ident.to_string().to_snake(), Span::call_site());
+            let struct_name = &variant.ident;
+            let cfg = cfg_attributes(&variant.attrs);
+
+            // positional fields are passed as `arg0`, `arg1`...
+            let arg_idents = variant
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(index, f)| match f.ident {
+                    Some(ref ident) => ident.clone(),
+                    None => Ident::new(&format!("arg{}", index), Span::call_site()),
+                })
+                .collect::<Vec<_>>();
+
+            // fields removed by a cfg are not passed to the constructor
+            let args = &variant
+                .fields
+                .iter()
+                .zip(arg_idents.iter())
+                .map(|(f, ident)| {
+                    let cfg = cfg_attributes(&f.attrs);
+                    let ty = &f.ty;
+
+                    quote! {
+                      #(#cfg)* #ident: #ty
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let arg_names = &variant
+                .fields
+                .iter()
+                .zip(arg_idents.iter())
+                .enumerate()
+                .map(|(index, (f, ident))| {
+                    let cfg = cfg_attributes(&f.attrs);
+                    if f.ident.is_some() {
+                        quote! { #(#cfg)* #ident }
+                    } else {
+                        let index = Index::from(index);
+                        quote! { #(#cfg)* #index: #ident }
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let struct_path = state_path(&namespace, struct_name);
+            let value = match variant.fields {
+                Fields::Unit => quote! { #struct_path },
+                _ => quote! { #struct_path { #(#arg_names),* } },
+            };
+
+            quote! {
+              #(#cfg)*
+              #vis fn #fn_name(#(#args),*) -> #machine_name {
+                #machine_name::#struct_name(#value)
+              }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let error_constructor = error.iter().map(|error| {
+        let fn_name = Ident::new(&error.to_string().to_snake(), Span::call_site());
+        quote! {
+          #vis fn #fn_name() -> #machine_name {
+            #machine_name::#error
+          }
+        }
+    });
+
+    let toks = quote! {
+      impl #machine_name {
+        #(#methods)*
+
+        #(#error_constructor)*
+      }
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+
+    // define the fieldless kind enum, with the error state first
+    let kind_ident = Ident::new(&format!("{}Kind", machine_name), Span::call_site());
+    let kind_names = error
+        .iter()
+        .map(|error| (Vec::new(), error.clone()))
+        .chain(cfgs.iter().cloned().zip(variants_names.iter().map(|v| (*v).clone())))
+        .collect::<Vec<_>>();
+
+    let kind_arms = variants_names
+        .iter()
+        .zip(cfgs.iter())
+        .map(|(v, cfg)| quote! { #(#cfg)* #machine_name::#v(_) => #kind_ident::#v, })
+        .collect::<Vec<_>>();
+
+    let error_arm = error.iter().map(|error| quote! { #machine_name::#error => #kind_ident::#error, });
+    let toks = quote! {
+      #[allow(dead_code)]
+      impl #machine_name {
+        #vis fn kind(&self) -> #kind_ident {
+          match self {
+            #(#error_arm)*
+            #(#kind_arms)*
+          }
+        }
+      }
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+    stream.extend(proc_macro::TokenStream::from(impl_kind_enum(vis, &kind_ident, &kind_names)));
+
+    // the initial state is built with the default value of its fields
+    if let Some(ref initial) = initial {
+        let variant = ast.variants.iter().find(|v| v.ident == *initial).unwrap();
+        let struct_path = state_path(&namespace, initial);
+        let value = match variant.fields {
+            Fields::Named(ref fields) => {
+                let names = fields.named.iter().map(|f| &f.ident);
+                quote! { #struct_path { #(#names: Default::default()),* } }
+            }
+            Fields::Unnamed(ref fields) => {
+                let defaults = fields.unnamed.iter().map(|_| quote! { Default::default() });
+                quote! { #struct_path(#(#defaults),*) }
+            }
+            Fields::Unit => quote! { #struct_path },
+        };
+
+        // the `Initial` state already has an `initial()` constructor
+        let constructor = if initial == "Initial" {
+            quote! {}
+        } else {
+            quote! {
+              #vis fn initial() -> #machine_name {
+                #machine_name::#initial(#value)
+              }
+            }
+        };
+        let cfg = &variant.attrs.iter().filter(|attr| attr.path.is_ident("cfg")).collect::<Vec<_>>();
+
+        let toks = quote! {
+          #(#cfg)*
+          impl #machine_name {
+            #constructor
+          }
+
+          #(#cfg)*
+          impl Default for #machine_name {
+            fn default() -> #machine_name {
+              #machine_name::#initial(#value)
+            }
+          }
+        };
+
+        stream.extend(proc_macro::TokenStream::from(toks));
+    }
+
+    let final_arms = ast
+        .variants
+        .iter()
+        .zip(cfgs.iter())
+        .filter(|(v, _)| finals.contains(&v.ident))
+        .map(|(v, cfg)| {
+            let name = &v.ident;
+            quote! { #(#cfg)* #machine_name::#name(_) => true, }
+        })
+        .collect::<Vec<_>>();
+    let is_error = error.iter().map(|error| {
+        let fn_name = Ident::new(&format!("is_{}", error.to_string().to_snake()), Span::call_site());
+        quote! {
+          #vis fn #fn_name(&self) -> bool {
+            match self {
+              #machine_name::#error => true,
+              _ => false,
+            }
+          }
+        }
+    });
+
+    // `transitions!` checks that the rows leaving a state start from one of these
+    let non_final_ident = machine_ident(machine_name, "NonFinal");
+    let non_final_impls = ast
+        .variants
+        .iter()
+        .zip(cfgs.iter())
+        .filter(|(v, _)| !finals.contains(&v.ident))
+        .map(|(v, cfg)| {
+            let state = state_path(&namespace, &v.ident);
+            quote! { #(#cfg)* impl #non_final_ident for #state {} }
+        })
+        .collect::<Vec<_>>();
+
+    let toks = quote! {
+      #[allow(dead_code)]
+      impl #machine_name {
+        #[allow(unreachable_patterns)]
+        #vis fn is_final(&self) -> bool {
+          match self {
+            #(#final_arms)*
+            _ => false,
+          }
+        }
+
+        #(#is_error)*
+      }
+
+      /// implemented by the states that are not marked `#[final]`
+      #vis trait #non_final_ident {}
+
+      #(#non_final_impls)*
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+
+    // helpers to inspect and extract the state of each variant
+    let variant_helpers = variants_names
+        .iter()
+        .zip(structs_names.iter())
+        .zip(cfgs.iter())
+        .map(|((variant, state), cfg)| {
+            let snake = variant.to_string().to_snake();
+            let is_ident = Ident::new(&format!("is_{}", snake), Span::call_site());
+            let as_ident = Ident::new(&format!("as_{}", snake), Span::call_site());
+            let as_mut_ident = Ident::new(&format!("as_{}_mut", snake), Span::call_site());
+            let into_ident = Ident::new(&format!("into_{}", snake), Span::call_site());
+
+            quote! {
+              #(#cfg)*
+              #[allow(unreachable_patterns)]
+              #vis fn #is_ident(&self) -> bool {
+                match self {
+                  #machine_name::#variant(_) => true,
+                  _ => false,
+                }
+              }
+
+              #(#cfg)*
+              #[allow(unreachable_patterns)]
+              #vis fn #as_ident(&self) -> Option<&#state> {
+                match self {
+                  #machine_name::#variant(state) => Some(state),
+                  _ => None,
+                }
+              }
+
+              #(#cfg)*
+              #[allow(unreachable_patterns)]
+              #vis fn #as_mut_ident(&mut self) -> Option<&mut #state> {
+                match self {
+                  #machine_name::#variant(state) => Some(state),
+                  _ => None,
+                }
+              }
+
+              #(#cfg)*
+              #[allow(unreachable_patterns)]
+              #vis fn #into_ident(self) -> Result<#state, #machine_name> {
+                match self {
+                  #machine_name::#variant(state) => Ok(state),
+                  machine => Err(machine),
+                }
+              }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let conversions = variants_names
+        .iter()
+        .zip(structs_names.iter())
+        .zip(cfgs.iter())
+        .map(|((variant, state), cfg)| {
+            let into_ident = Ident::new(&format!("into_{}", variant.to_string().to_snake()), Span::call_site());
+
+            quote! {
+              #(#cfg)*
+              impl From<#state> for #machine_name {
+                fn from(state: #state) -> #machine_name {
+                  #machine_name::#variant(state)
+                }
+              }
+
+              #(#cfg)*
+              impl ::std::convert::TryFrom<#machine_name> for #state {
+                type Error = #machine_name;
+
+                fn try_from(machine: #machine_name) -> Result<#state, #machine_name> {
+                  #machine_name::#into_ident(machine)
+                }
+              }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let toks = quote! {
+      #[allow(dead_code)]
+      impl #machine_name {
+        #(#variant_helpers)*
+      }
+
+      #(#conversions)*
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+
+    let mut markers = Vec::new();
+    if let Some(ref initial) = initial {
+        markers.push("__start [ shape = point ];".to_string());
+        markers.push(format!("__start -> {};", initial));
+    }
+    for state in finals.iter() {
+        markers.push(format!("{} [ shape = doublecircle ];", state));
+    }
+    write_dot(machine_name, &markers, |line| !is_dot_marker(line));
+
+    if let Some(ref context) = options.context {
+        stream.extend(proc_macro::TokenStream::from(impl_context(vis, machine_name, context)));
+    }
+
+    (machine_name, stream)
+}
+
+/// the current state along with the context shared by all states. `transitions!`
+/// refers to the context type through the `<Name>Context` alias
+fn impl_context(vis: &TokenStream2, machine_name: &Ident, context: &Type) -> TokenStream2 {
+    let context_ident = machine_ident(machine_name, "Context");
+    let holder_ident = machine_ident(machine_name, "Machine");
+
+    quote! {
+      #vis type #context_ident = #context;
+
+      #[allow(dead_code)]
+      #vis struct #holder_ident {
+        // only taken while the state executes a message
+        state: Option<#machine_name>,
+        context: #context_ident,
+      }
+
+      #[allow(dead_code)]
+      impl #holder_ident {
+        #vis fn new(state: #machine_name, context: #context_ident) -> Self {
+          #holder_ident { state: Some(state), context }
+        }
+
+        #vis fn state(&self) -> &#machine_name {
+          self.state.as_ref().expect("the state is only taken during a transition")
+        }
+
+        #vis fn context(&self) -> &#context_ident {
+          &self.context
+        }
+
+        #vis fn context_mut(&mut self) -> &mut #context_ident {
+          &mut self.context
+        }
+
+        #vis fn into_parts(self) -> (#machine_name, #context_ident) {
+          (self.state.expect("the state is only taken during a transition"), self.context)
+        }
+      }
+    }
+}
+
+/// generates a `Copy` enum naming each variant, with `name()`, `Display` and `FromStr`.
+/// Each variant comes with the cfg attributes it is declared under
+fn impl_kind_enum(vis: &TokenStream2, kind_ident: &Ident, kinds: &[(Vec<&Attribute>, Ident)]) -> TokenStream2 {
+    let variants = kinds
+        .iter()
+        .map(|(cfg, v)| quote! { #(#cfg)* #v })
+        .collect::<Vec<_>>();
+    let name_arms = kinds
+        .iter()
+        .map(|(cfg, v)| {
+            let name = v.to_string();
+            quote! { #(#cfg)* #kind_ident::#v => #name, }
+        })
+        .collect::<Vec<_>>();
+    let parse_arms = kinds
+        .iter()
+        .map(|(cfg, v)| {
+            let name = v.to_string();
+            quote! { #(#cfg)* #name => Ok(#kind_ident::#v), }
+        })
+        .collect::<Vec<_>>();
+
+    quote! {
+      #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+      #[allow(dead_code)]
+      #vis enum #kind_ident {
+        #(#variants),*
+      }
+
+      #[allow(dead_code)]
+      impl #kind_ident {
+        #vis fn name(&self) -> &'static str {
+          match self {
+            #(#name_arms)*
+          }
+        }
+      }
+
+      impl std::fmt::Display for #kind_ident {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+          f.write_str(self.name())
+        }
+      }
+
+      impl std::str::FromStr for #kind_ident {
+        type Err = String;
+
+        fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+          match s {
+            #(#parse_arms)*
+            _ => Err(format!("unknown {}: {}", stringify!(#kind_ident), s)),
+          }
+        }
+      }
+    }
+}
+
+/// what `transitions!` and `methods!` need to know about the machine, passed
+/// in brackets by the macro `machine!` defines
+#[derive(Debug)]
+struct MachineDefaults {
+    pub vis: TokenStream2,
+    /// the error state, none with the `no_error` option
+    pub error: Option<Ident>,
+    /// the states are defined in a module
+    pub namespace: bool,
+    /// the machine has a context
+    pub context: bool,
+}
+
+fn check_namespace(machine_name: &Ident, namespace: bool, defaults: &MachineDefaults) -> Result<()> {
+    if namespace && !defaults.namespace {
+        return Err(syn::Error::new(
+            machine_name.span(),
+            format!("`{}` does not define its states in a module", machine_name),
+        ));
+    }
+    Ok(())
+}
+
+impl MachineDefaults {
+    fn parse_optional(input: ParseStream) -> Result<Option<Self>> {
+        if !input.peek(syn::token::Bracket) {
+            return Ok(None);
+        }
+
+        let content;
+        bracketed!(content in input);
+        let vis: Visibility = content.parse()?;
+        let mut defaults = MachineDefaults {
+            vis: item_vis(&vis),
+            error: None,
+            namespace: false,
+            context: false,
+        };
+        while !content.is_empty() {
+            let _: Token![,] = content.parse()?;
+            let name: Ident = content.parse()?;
+            match name.to_string().as_str() {
+                "error" => {
+                    let _: Token![=] = content.parse()?;
+                    defaults.error = Some(content.parse()?);
+                }
+                "namespace" => defaults.namespace = true,
+                "context" => defaults.context = true,
+                _ => {}
+            }
+        }
+
+        Ok(Some(defaults))
+    }
+}
+
+#[derive(Debug)]
+struct Transitions {
+    /// the defaults given by `machine!`, when they were looked up
+    pub defaults: Option<MachineDefaults>,
+    /// visibility of the generated items, written before the machine name
+    pub vis: Visibility,
+    pub machine_name: Ident,
+    pub options: TransitionsOptions,
+    pub transitions: Vec<Transition>,
+    pub timeouts: Vec<Timeout>,
+    pub defers: Vec<Defer>,
+    /// the rows as written, forwarded by groups to `transitions_finish!`
+    pub rows: TokenStream2,
+}
+
+/// options accepted between the machine name and the transitions list
+#[derive(Debug, Default)]
+struct TransitionsOptions {
+    /// generate the `<Name>Instrumented` wrapper recording metrics
+    pub metrics: bool,
+    /// pass a queue to the handlers and generate the `<Name>Runner`
+    pub runner: bool,
+    /// the type of the commands emitted by the handlers
+    pub output: Option<Type>,
+    /// pass the context declared in `machine!` to the handlers
+    pub context: bool,
+    /// pass the messages by reference instead of by value
+    pub by_ref: bool,
+    /// declare the handlers in a `<State>Transitions` trait per state
+    pub traits: bool,
+    /// messages defined by the macro, written like enum variants
+    pub messages: Vec<Variant>,
+    /// only declare the rows, merged with other groups by `transitions_finish!`
+    pub group: Option<Ident>,
+    /// the machine defines its state structs in a module
+    pub namespace: bool,
+    /// the name given to the error state in `machine!`
+    pub error: Option<Ident>,
+    /// the machine has no error state, every state handles every message
+    pub no_error: bool,
+    /// take the machine's visibility from the macro `machine!` defines
+    pub inherit: bool,
+    /// the traits derived by the messages enum, instead of `Clone, Debug, PartialEq`
+    pub derive: Option<Vec<Path>>,
+}
+
+impl TransitionsOptions {
+    fn parse_option(&mut self, input: ParseStream) -> Result<()> {
+        let name: Ident = input.parse()?;
+        match name.to_string().as_str() {
+            "metrics" => self.metrics = true,
+            "runner" => self.runner = true,
+            "context" => self.context = true,
+            "by_ref" => self.by_ref = true,
+            "traits" => self.traits = true,
+            "namespace" => self.namespace = true,
+            "no_error" => self.no_error = true,
+            "inherit" => self.inherit = true,
+            "error" => {
+                let _: Token![=] = input.parse()?;
+                self.error = Some(input.parse()?);
+            }
+            "messages" => {
+                let content;
+                braced!(content in input);
+                let messages = content.parse_terminated::<Variant, Token![,]>(Variant::parse)?;
+                for message in messages.iter() {
+                    if let Some((_, ref discriminant)) = message.discriminant {
+                        return Err(syn::Error::new_spanned(discriminant, "messages cannot have a discriminant"));
+                    }
+                }
+                self.messages.extend(messages);
+            }
+            "output" => {
+                let _: Token![=] = input.parse()?;
+                self.output = Some(input.parse()?);
+            }
+            "derive" => {
+                let content;
+                parenthesized!(content in input);
+                let derive = content.parse_terminated::<Path, Token![,]>(Path::parse_mod_style)?;
+                self.derive = Some(derive.into_iter().collect());
+            }
+            "group" => {
+                let _: Token![=] = input.parse()?;
+                self.group = Some(input.parse()?);
+            }
+            _ => return Err(syn::Error::new(name.span(), format!("unknown option `{}`", name))),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Transition {
+    pub start: Ident,
+    pub message: Type,
+    /// names the message variant and the `on_*` methods
+    pub name: Ident,
+    pub end: Vec<Ident>,
+    /// the handler, written inline as a closure taking the state and the message
+    pub body: Option<ExprClosure>,
+}
+
+impl Transitions {
+    /// the written visibility overrides the machine's one
+    fn item_vis(&self) -> TokenStream2 {
+        match (&self.vis, self.defaults.as_ref()) {
+            (&Visibility::Inherited, Some(defaults)) => defaults.vis.clone(),
+            (vis, _) => item_vis(vis),
+        }
+    }
+
+    /// the error state, taken from the machine with the `inherit` option
+    fn error_ident(&self) -> Option<Ident> {
+        error_ident(&self.options.error, self.options.no_error)
+    }
+}
+
+impl Parse for Transitions {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let defaults = MachineDefaults::parse_optional(input)?;
+        let vis: Visibility = input.parse()?;
+        let machine_name: Ident = input.parse()?;
+        let _: Token![,] = input.parse()?;
+
+        let mut options = TransitionsOptions::default();
+        let mut option_count = 0;
+        while !input.peek(syn::token::Bracket) {
+            let span = input.cursor().span();
+            options.parse_option(input)?;
+            let _: Token![,] = input.parse()?;
+
+            option_count += 1;
+            if options.group.is_some() && option_count > 1 {
+                return Err(syn::Error::new(
+                    span,
+                    "a group only declares rows, the options go in `transitions_finish!`",
+                ));
+            }
+        }
+
+        // the written options are checked against the machine, when it was
+        // looked up, then replaced with its settings
+        if let Some(ref defaults) = defaults {
+            let written = if options.no_error {
+                Some(None)
+            } else {
+                options.error.as_ref().map(|error| Some(error.clone()))
+            };
+            match written {
+                Some(ref error) if *error != defaults.error => {
+                    let span = options.error.as_ref().map(|e| e.span()).unwrap_or_else(|| machine_name.span());
+                    let message = match defaults.error {
+                        Some(ref error) => format!("the error state of `{}` is `{}`", machine_name, error),
+                        None => format!("`{}` has no error state", machine_name),
+                    };
+                    return Err(syn::Error::new(span, message));
+                }
+                _ => {}
+            }
+            check_namespace(&machine_name, options.namespace, defaults)?;
+            if options.context && !defaults.context {
+                return Err(syn::Error::new(
+                    machine_name.span(),
+                    format!("`{}` has no context", machine_name),
+                ));
+            }
+
+            options.error = defaults.error.clone();
+            options.no_error = defaults.error.is_none();
+            options.namespace = defaults.namespace;
+            options.context = defaults.context;
+        }
+
+        let content;
+        bracketed!(content in input);
+        let mut rows: TokenStream2 = content.fork().parse()?;
+
+        trace!("content: {:?}", content);
+        let mut transitions = Vec::new();
+        let mut timeouts: Vec<Timeout> = Vec::new();
+        let mut defers = Vec::new();
+
+        loop {
+            match content.parse()? {
+                Row::Transition(t) => transitions.push(t),
+                Row::Defer(d) => {
+                    if !options.runner && options.group.is_none() {
+                        return Err(syn::Error::new(
+                            d.start.span(),
+                            "deferring messages requires the `runner` option",
+                        ));
+                    }
+                    defers.push(d);
+                }
+                Row::Timeout(t) => {
+                    if timeouts.iter().any(|other| other.start == t.start) {
+                        return Err(syn::Error::new(
+                            t.start.span(),
+                            format!("state `{}` already has a timeout", t.start),
+                        ));
+                    }
+                    timeouts.push(t);
+                }
+            }
+
+            let lookahead = content.lookahead1();
+            if lookahead.peek(Token![,]) {
+                let _: Token![,] = content.parse()?;
+                if content.is_empty() {
+                    break;
+                }
+            } else {
+                rows.extend(quote! { , });
+                break;
+            }
+        }
+
+        let mut names: Vec<(&Ident, &Type)> = Vec::new();
+        let named = transitions
+            .iter()
+            .map(|t| (&t.name, &t.message))
+            .chain(defers.iter().map(|d| (&d.name, &d.message)));
+        for (name, message) in named {
+            match names.iter().find(|(other, _)| *other == name) {
+                Some((_, other)) if *other != message => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        format!("`{}` already names another message type, rename one of them with `as`", name),
+                    ));
+                }
+                Some(_) => {}
+                None => names.push((name, message)),
+            }
+        }
+
+        for message in options.messages.iter() {
+            let used = names.iter().any(|(name, _)| *name == &message.ident);
+            if !used {
+                return Err(syn::Error::new(
+                    message.ident.span(),
+                    format!("message `{}` is not used in any transition", message.ident),
+                ));
+            }
+        }
+
+        // the closure parameters are bound to the state, the message, then the
+        // arguments added by the options, in the handlers' order
+        let extra = [options.context, options.output.is_some(), options.runner]
+            .iter()
+            .filter(|enabled| **enabled)
+            .count();
+        let bodies = transitions
+            .iter()
+            .filter_map(|t| t.body.as_ref().map(|body| (body, 2 + extra)))
+            .chain(timeouts.iter().filter_map(|t| t.body.as_ref().map(|body| (body, 1 + extra))));
+        for (body, max) in bodies.filter(|_| options.group.is_none()) {
+            if body.inputs.len() > max {
+                return Err(syn::Error::new(
+                    body.or2_token.span(),
+                    format!("the handler takes at most {} parameters", max),
+                ));
+            }
+        }
+
+        for d in defers.iter() {
+            if transitions.iter().any(|t| t.start == d.start && t.message == d.message) {
+                return Err(syn::Error::new(
+                    d.start.span(),
+                    format!("state `{}` cannot both handle and defer `{}`", d.start, d.message.clone().into_token_stream()),
+                ));
+            }
+        }
+
+        if options.group.is_some() && vis != Visibility::Inherited {
+            return Err(syn::Error::new_spanned(
+                vis,
+                "a group only declares rows, the visibility goes in `transitions_finish!`",
+            ));
+        }
+
+        Ok(Transitions {
+            defaults,
+            vis,
+            machine_name,
+            options,
+            transitions,
+            timeouts,
+            defers,
+            rows,
+        })
+    }
+}
+
+/// a transition triggered when the machine stayed too long in the start state
+#[derive(Debug)]
+struct Timeout {
+    pub start: Ident,
+    pub label: String,
+    pub duration: TokenStream2,
+    pub end: Vec<Ident>,
+    /// the handler, written inline as a closure taking the state
+    pub body: Option<ExprClosure>,
+}
+
+/// a message kept by the runner until the machine reaches a state accepting it
+#[derive(Debug)]
+struct Defer {
+    pub start: Ident,
+    pub message: Type,
+    pub name: Ident,
+}
+
+enum Row {
+    Transition(Transition),
+    Timeout(Timeout),
+    Defer(Defer),
+}
+
+impl Parse for Row {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let left;
+        parenthesized!(left in input);
+
+        let start: Ident = left.parse()?;
+        let _: Token![,] = left.parse()?;
+
+        let is_timeout = left.peek2(syn::token::Paren)
+            && left.fork().parse::<Ident>().map(|i| i == "after").unwrap_or(false);
+
+        if is_timeout {
+            let _: Ident = left.parse()?;
+            let content;
+            parenthesized!(content in left);
+            let (label, duration) = parse_duration(&content)?;
+
+            let _: Token![=>] = input.parse()?;
+            let end = parse_end_states(input)?;
+            let body = parse_body(input)?;
+
+            Ok(Row::Timeout(Timeout {
+                start,
+                label,
+                duration,
+                end,
+                body,
+            }))
+        } else {
+            let message: Type = left.parse()?;
+            let name = if left.peek(Token![as]) {
+                let _: Token![as] = left.parse()?;
+                left.parse()?
+            } else {
+                match type_last_ident(&message) {
+                    Some(ident) => ident.clone(),
+                    None => {
+                        return Err(syn::Error::new_spanned(
+                            &message,
+                            "this message type needs a name, add `as Name` after it",
+                        ))
+                    }
+                }
+            };
+
+            let _: Token![=>] = input.parse()?;
+            if input.fork().parse::<Ident>().map(|i| i == "defer").unwrap_or(false) {
+                let _: Ident = input.parse()?;
+                return Ok(Row::Defer(Defer { start, message, name }));
+            }
+            let end = parse_end_states(input)?;
+            let body = parse_body(input)?;
+
+            Ok(Row::Transition(Transition {
+                start,
+                message,
+                name,
+                end,
+                body,
+            }))
+        }
+    }
+}
+
+fn parse_body(input: ParseStream) -> Result<Option<ExprClosure>> {
+    if input.peek(Token![|]) || input.peek(Token![||]) || input.peek(Token![move]) {
+        let body: ExprClosure = input.parse()?;
+        if let Some(capture) = body.capture {
+            return Err(syn::Error::new(
+                capture.span(),
+                "an inline handler captures nothing, remove `move`",
+            ));
+        }
+        if let ReturnType::Type(ref arrow, _) = body.output {
+            return Err(syn::Error::new(
+                arrow.span(),
+                "the return type of an inline handler comes from the end states, remove it",
+            ));
+        }
+        Ok(Some(body))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_end_states(input: ParseStream) -> Result<Vec<Ident>> {
+    let end = match input.parse::<Ident>() {
+        Ok(i) => vec![i],
+        Err(_) => {
+            let content;
+            bracketed!(content in input);
+
+            //println!("content: {:?}", content);
+            let mut states = Vec::new();
+
+            let t: Ident = content.parse()?;
+            states.push(t);
+
+            loop {
+                let lookahead = content.lookahead1();
+                if lookahead.peek(Token![,]) {
+                    let _: Token![,] = content.parse()?;
+                    let t: Ident = content.parse()?;
+                    states.push(t);
+                } else {
+                    break;
+                }
+            }
+
+            states
+        }
+    };
+
+    Ok(end)
+}
+
+/// parses a duration literal with a unit suffix (`500ms`, `5s`, `2m`, `1h`),
+/// or any expression evaluating to a `std::time::Duration`
+fn parse_duration(input: ParseStream) -> Result<(String, TokenStream2)> {
+    if input.peek(syn::Lit) {
+        let lit: syn::Lit = input.parse()?;
+        let text = lit.clone().into_token_stream().to_string();
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (value, unit) = text.split_at(split);
+
+        let factor = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            _ => 0,
+        };
+        let millis = match value.parse::<u64>() {
+            Ok(v) if factor > 0 => v.checked_mul(factor),
+            _ => {
+                return Err(syn::Error::new(
+                    lit.span(),
+                    "expected a duration like `500ms`, `5s`, `2m` or `1h`",
+                ))
+            }
+        };
+        let millis = match millis {
+            Some(millis) => millis,
+            None => return Err(syn::Error::new(lit.span(), "this duration overflows a u64 of milliseconds")),
+        };
+
+        Ok((text.clone(), quote! { std::time::Duration::from_millis(#millis) }))
+    } else {
+        let expr: Expr = input.parse()?;
+        Ok((expr.clone().into_token_stream().to_string(), expr.into_token_stream()))
+    }
+}
+
+impl Transitions {
+    pub fn render(&self) {
+        let mut lines = Vec::new();
+
+        let mut edges = Vec::new();
+        for transition in self.transitions.iter() {
+            for state in transition.end.iter() {
+                edges.push((&transition.start, &transition.message, state));
+            }
+        }
+
+        for edge in edges.iter() {
+            lines.push(format!("{} -> {} [ label = \"{}\" ];", edge.0, edge.2, edge.1.into_token_stream()));
+        }
+
+        for defer in self.defers.iter() {
+            lines.push(format!(
+                "{} -> {} [ label = \"defer {}\", style = dotted ];",
+                defer.start,
+                defer.start,
+                defer.message.clone().into_token_stream()
+            ));
+        }
+
+        for timeout in self.timeouts.iter() {
+            for state in timeout.end.iter() {
+                lines.push(format!(
+                    "{} -> {} [ label = \"after({})\", style = dashed ];",
+                    timeout.start, state, timeout.label
+                ));
+            }
+        }
+
+        write_dot(&self.machine_name, &lines, is_dot_marker);
+    }
+}
+
+/// the initial and final markers written by `machine!`
+fn is_dot_marker(line: &str) -> bool {
+    line.starts_with("__start") || line.contains("shape = doublecircle")
+}
+
+/// `machine!` and `transitions!` share the DOT file of a machine: each one
+/// rewrites its own lines, keeping the lines of the other
+fn write_dot<F: Fn(&str) -> bool>(machine_name: &Ident, lines: &[String], keep: F) {
+    let file_name = format!("target/machine/{}.dot", machine_name.to_string().to_lowercase());
+    let _ = create_dir("target/machine");
+
+    let previous = std::fs::read_to_string(&file_name).unwrap_or_default();
+    let kept = previous
+        .lines()
+        .filter(|line| !line.starts_with("digraph") && *line != "}")
+        .filter(|line| keep(line))
+        .map(|line| line.to_string());
+
+    let mut file = File::create(&file_name).expect("error opening dot file");
+    file.write_all(format!("digraph {} {{\n", machine_name).as_bytes())
+        .expect("error writing to dot file");
+    for line in kept.chain(lines.iter().cloned()) {
+        file.write_all(format!("{}\n", line).as_bytes())
+            .expect("error writing to dot file");
+    }
+    file.write_all(&b"}"[..])
+        .expect("error writing to dot file");
+    file.flush().expect("error flushhing dot file");
+}
+
+#[proc_macro]
+pub fn transitions(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    //println!("\ninput: {:?}", input);
+    let mut stream = proc_macro::TokenStream::new();
+
+    let tokens = TokenStream2::from(input.clone());
+    let transitions = parse_macro_input!(input as Transitions);
+    trace!("\nparsed transitions: {:#?}", transitions);
+
+    if let Some(ref group) = transitions.options.group {
+        return proc_macro::TokenStream::from(impl_group(&transitions.machine_name, group, &transitions.rows));
+    }
+
+    // with the `inherit` option, the machine's settings are looked up first
+    if transitions.defaults.is_none() && transitions.options.inherit {
+        return proc_macro::TokenStream::from(impl_defaults_lookup(&transitions.machine_name, "transitions", tokens));
+    }
+
+    transitions.render();
+
+    let vis = &transitions.item_vis();
+    let error = transitions.error_ident();
+    let error_span = match transitions.options.error {
+        Some(ref error) => error.span(),
+        None => transitions.machine_name.span(),
+    };
+    let span = transitions.machine_name.span();
+    stream.extend(proc_macro::TokenStream::from(impl_defaults_check(
+        &transitions.machine_name,
+        &[
+            (error_setting(&error), error_span),
+            (namespace_setting(transitions.options.namespace), span),
+            (context_setting(transitions.options.context), span),
+        ],
+    )));
+    let machine_name = transitions.machine_name;
+
+    // messages are keyed by their name, in the order they appear
+    let mut messages: Vec<(&Ident, &Type, Vec<_>)> = Vec::new();
+    for t in transitions.transitions.iter() {
+        let mv = (&t.start, &t.end, t.body.is_some());
+        match messages.iter_mut().find(|(name, _, _)| *name == &t.name) {
+            Some((_, _, moves)) => moves.push(mv),
+            None => messages.push((&t.name, &t.message, vec![mv])),
+        }
+    }
+
+    // states deferring each message, which must still be part of the messages enum
+    let mut deferred = HashMap::new();
+    for d in transitions.defers.iter() {
+        if !messages.iter().any(|(name, _, _)| *name == &d.name) {
+            messages.push((&d.name, &d.message, Vec::new()));
+        }
+        deferred.entry(&d.name).or_insert(Vec::new()).push(&d.start);
+    }
+
+    //let mut message_types = transitions.transitions.iter().map(|t| &t.message).collect::<Vec<_>>();
+
+    let mut type_arguments = HashSet::new();
+    for t in transitions.transitions.iter() {
+      let mut args = type_args(&t.message);
+      type_arguments.extend(args.drain());
+    }
+    for d in transitions.defers.iter() {
+      let mut args = type_args(&d.message);
+      type_arguments.extend(args.drain());
+    }
+
+    let type_arguments = &reorder_type_arguments(type_arguments);
+
+    // create an enum from the messages
+    let message_enum_ident = Ident::new(
+        &format!("{}Messages", &machine_name.to_string()),
+        Span::call_site(),
+    );
+    let structs_names = messages.iter().map(|(_, ty, _)| *ty).collect::<Vec<_>>();
+    let variants_names = &messages.iter().map(|(name, _, _)| *name).collect::<Vec<_>>();
+
+
+    let type_arg_toks = if type_arguments.is_empty() {
+      quote!{}
+    } else {
+      quote!{
+        < #(#type_arguments),* >
+      }
+    };
+
+    let derive = match transitions.options.derive {
+        Some(ref derive) => quote! { #[derive(#(#derive),*)] },
+        None => quote! { #[derive(Clone,Debug,PartialEq)] },
+    };
+
+    // define the state enum
+    let toks = quote! {
+      #derive
+      #vis enum #message_enum_ident #type_arg_toks {
+        #(#variants_names(#structs_names)),*
+      }
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+
+    // messages defined in the macro
+    for message in transitions.options.messages.iter() {
+        let attrs = &message.attrs;
+        let name = &message.ident;
+        let fields = &message.fields;
+        let semicolon = match message.fields {
+            Fields::Named(_) => quote! {},
+            _ => quote! { ; },
+        };
+
+        let toks = quote! {
+          #(#attrs)*
+          #[derive(Clone, Debug, PartialEq)]
+          #vis struct #name #fields #semicolon
+
+          impl #type_arg_toks From<#name> for #message_enum_ident #type_arg_toks {
+            fn from(input: #name) -> Self {
+              #message_enum_ident::#name(input)
+            }
+          }
+        };
+
+        stream.extend(proc_macro::TokenStream::from(toks));
+    }
+
+    // and its fieldless counterpart
+    let message_kind_ident = Ident::new(
+        &format!("{}MessageKind", &machine_name.to_string()),
+        Span::call_site(),
+    );
+    let message_kinds = variants_names.iter().map(|v| (*v).clone()).collect::<Vec<_>>();
+    let message_kind_arms = message_kinds
+        .iter()
+        .map(|v| quote! { #message_enum_ident::#v(_) => #message_kind_ident::#v, })
+        .collect::<Vec<_>>();
+    let toks = quote! {
+      #[allow(dead_code)]
+      impl #type_arg_toks #message_enum_ident #type_arg_toks {
+        #vis fn kind(&self) -> #message_kind_ident {
+          match self {
+            #(#message_kind_arms)*
+          }
+        }
+      }
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+    stream.extend(proc_macro::TokenStream::from(impl_kind_enum(
+        vis,
+        &message_kind_ident,
+        &message_kinds.iter().map(|v| (Vec::new(), v.clone())).collect::<Vec<_>>(),
+    )));
+
+    let args = HandlerArgs::new(vis, &machine_name, error, &transitions.options, &type_arg_toks);
+    let params = args.params();
+    let forward = args.forward();
+    let ret = args.ret(&machine_name);
+
+    // the error state's handlers are implemented on a marker struct
+    let states = transitions
+        .transitions
+        .iter()
+        .flat_map(|t| std::iter::once(&t.start).chain(t.end.iter()))
+        .chain(transitions.timeouts.iter().flat_map(|t| std::iter::once(&t.start).chain(t.end.iter())))
+        .chain(transitions.defers.iter().map(|d| &d.start))
+        .collect::<Vec<_>>();
+    let error_marker = match args.error {
+        Some(ref error) if states.contains(&error) => args.error_marker(error),
+        _ => None,
+    };
+    if let Some(marker) = error_marker {
+        let toks = quote! {
+          #[derive(Clone, Copy, Debug, Default, PartialEq)]
+          #vis struct #marker;
+        };
+
+        stream.extend(proc_macro::TokenStream::from(toks));
+    }
+
+    // the states marked `#[final]` in `machine!` do not implement
+    // `<Name>NonFinal`, so a row leaving one of them fails to compile
+    let non_final_ident = machine_ident(&machine_name, "NonFinal");
+    let leaving = transitions
+        .transitions
+        .iter()
+        .map(|t| (&t.start, &t.end))
+        .chain(transitions.timeouts.iter().map(|t| (&t.start, &t.end)))
+        .filter(|(start, end)| args.error_marker(start).is_none() && end.iter().any(|state| state != *start))
+        .map(|(start, _)| {
+            let state = args.state(start);
+            quote_spanned! {start.span()=>
+              assert_non_final::<#state>();
+            }
+        })
+        .collect::<Vec<_>>();
+    if !leaving.is_empty() {
+        let toks = quote! {
+          const _: () = {
+            fn assert_non_final<T: #non_final_ident>() {}
+
+            #[allow(dead_code)]
+            fn leaving_rows() {
+              #(#leaving)*
+            }
+          };
+        };
+
+        stream.extend(proc_macro::TokenStream::from(toks));
+    }
+
+    // transitions with multiple end states can return an outcome enum
+    // restricted to the declared end states
+    for t in transitions.transitions.iter().filter(|t| t.end.len() > 1) {
+        let outcome_ident = args.outcome_ident(&t.start, &t.name);
+        stream.extend(proc_macro::TokenStream::from(impl_outcome(&args, &machine_name, &outcome_ident, &t.end)));
+    }
+
+    // handlers written inline in the rows
+    for t in transitions.transitions.iter() {
+        if let Some(ref body) = t.body {
+            let signature = HandlerSignature::transition(t, &args, &type_arg_toks);
+            stream.extend(proc_macro::TokenStream::from(impl_inline_handler(&t.start, &signature, body, &args)));
+        }
+    }
+
+    // with the `traits` option, the other handlers are declared in a trait per state
+    if transitions.options.traits {
+        let mut states: Vec<(&Ident, Vec<HandlerSignature>)> = Vec::new();
+        let signatures = transitions
+            .transitions
+            .iter()
+            .filter(|t| t.body.is_none())
+            .map(|t| (&t.start, HandlerSignature::transition(t, &args, &type_arg_toks)))
+            .chain(
+                transitions
+                    .timeouts
+                    .iter()
+                    .filter(|t| t.body.is_none())
+                    .map(|t| (&t.start, HandlerSignature::timeout(t, &args, &type_arg_toks))),
+            );
+        for (start, signature) in signatures {
+            match states.iter_mut().find(|(state, _)| *state == start) {
+                Some((_, signatures)) => signatures.push(signature),
+                None => states.push((start, vec![signature])),
+            }
+        }
+
+        for (start, signatures) in states.iter() {
+            let trait_ident = args.state_item_ident(start, "Transitions");
+            let methods = signatures.iter().map(|signature| signature.to_tokens(&args));
+
+            let toks = quote! {
+              #vis trait #trait_ident {
+                #(#methods;)*
+              }
+            };
+
+            stream.extend(proc_macro::TokenStream::from(toks));
+        }
+    }
+
+    let kind_ident = Ident::new(&format!("{}Kind", &machine_name.to_string()), Span::call_site());
+
+    // the arms handling a message in each state. `execute_with` also notifies
+    // its observer, with the message kept in `message`
+    let observed_message = if args.by_ref { quote!{ message } } else { quote!{ &message } };
+    let message_match = |id: &Ident, moves: &[(&Ident, &Vec<Ident>, bool)], observed: bool| {
+        let fn_ident = Ident::new(
+          &format!("on_{}", id.to_string().to_snake()),
+          Span::call_site(),
+          );
+        let message_name = id.to_string();
+        let mv = moves.iter().map(|(start, end, inline)| {
+          let call = args.call(start, &fn_ident, *inline, Some(quote!{ input }));
+          let next = if end.len() == 1 {
+            args.state_value(&end[0], call)
+          } else {
+            quote!{ #machine_name::from(#call) }
+          };
+
+          let next = log_transition(&machine_name, start, &message_name, next);
+          let pattern = args.state_pattern(start, quote!{ state });
+          let bind = args.bind_state(start);
+          let notify = if observed {
+            quote!{
+              let next = #next;
+              observer.on_transition(from, #observed_message, next.kind());
+              next
+            }
+          } else {
+            next
+          };
+          quote!{
+            #pattern => {
+              #bind
+              #notify
+            }
+          }
+        }).collect::<Vec<_>>();
+
+        // the runner's queue keeps deferred messages, the state does not change
+        let owned_input = if args.by_ref { quote!{ input.clone() } } else { quote!{ input } };
+        let defer_mv = deferred.get(id).map(|starts: &Vec<&Ident>| starts.iter().map(|start| {
+          let log = log_deferred(&machine_name, start, &message_name);
+          let pattern = args.state_pattern(start, quote!{ state });
+          let bind = args.bind_state(start);
+          let same = args.state_value(start, quote!{ state });
+          quote!{
+            #pattern => {
+              #log
+              #bind
+              queue.defer(#message_enum_ident::#id(#owned_input));
+              #same
+            }
+          }
+        }).collect::<Vec<_>>()).unwrap_or_default();
+        // without an error state, the match must cover every state
+        let rejected = args.error.iter().map(|error| {
+          let rejected = log_rejected(&machine_name, error, &message_name);
+          if observed {
+            quote!{
+              _ => {
+                observer.on_rejected(from, #observed_message);
+                #rejected
+              }
+            }
+          } else {
+            quote!{ _ => #rejected, }
+          }
+        }).collect::<Vec<_>>();
+
+        args.collect(quote! {
+          match self {
+            #(#mv)*
+            #(#defer_mv)*
+            #(#rejected)*
+          }
+        })
+    };
+
+    let functions = messages
+      .iter()
+      .map(|(id, msg, moves)| {
+        let fn_ident = Ident::new(
+          &format!("on_{}", id.to_string().to_snake()),
+          Span::call_site(),
+          );
+
+        // the queue is parameterized by the type arguments of every message
+        let type_arg_toks = if args.queue.is_some() {
+          type_arg_toks.clone()
+        } else {
+          type_arguments_toks(&reorder_type_arguments(type_args(msg)))
+        };
+
+        let input_ty = args.input(quote! { #msg });
+        let next = message_match(id, moves, false);
+        quote! {
+          #vis fn #fn_ident #type_arg_toks(self, input: #input_ty #params) -> #ret {
+            #next
+          }
+        }
+      })
+    .collect::<Vec<_>>();
+
+    let matches = messages
+      .iter()
+      .map(|(id, _, _)| {
+        let fn_ident = Ident::new(
+          &format!("on_{}", id.to_string().to_snake()),
+          Span::call_site(),
+          );
+
+          quote!{
+            #message_enum_ident::#id(message) => self.#fn_ident(message #forward),
+          }
+
+      })
+    .collect::<Vec<_>>();
+
+    /*let type_arg_toks = if type_arguments.is_empty() {
+      quote!{}
+    } else {
+      quote!{
+        < #(#type_arguments),* >
+      }
+    };*/
+
+    let log_execute = if cfg!(feature = "runtime-log") {
+        quote! {
+          {
+            extern crate log;
+            log::trace!("{}: {} received {}", stringify!(#machine_name), self.kind(), input.kind());
+          }
+        }
+    } else {
+        quote! {}
+    };
+
+    let error = &args
+        .error
+        .iter()
+        .map(|error| args.without_output(quote! { #machine_name::#error }))
+        .collect::<Vec<_>>();
+    let messages_ty = &args.input(quote! { #message_enum_ident #type_arg_toks });
+    let execute = quote! {
+      #vis fn execute #type_arg_toks(self, input: #messages_ty #params) -> #ret {
+        #log_execute
+        match input {
+          #(#matches)*
+          #(_ => #error,)*
+        }
+      }
+    };
+
+    // queries derived from the transition table
+
+    let mut accepted: Vec<(&Ident, Vec<&Ident>)> = Vec::new();
+    for t in transitions.transitions.iter() {
+        let id = &t.name;
+        match accepted.iter_mut().find(|(start, _)| *start == &t.start) {
+            Some((_, ids)) => ids.push(id),
+            None => accepted.push((&t.start, vec![id])),
+        }
+    }
+
+    let can_handle_arms = transitions
+        .transitions
+        .iter()
+        .map(|t| {
+            let pattern = args.state_pattern(&t.start, quote! { _ });
+            let id = &t.name;
+            quote! {
+              (#pattern, #message_enum_ident::#id(_)) => true,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let accepted_arms = accepted
+        .iter()
+        .map(|(start, ids)| {
+            let pattern = args.state_pattern(start, quote! { _ });
+            let kinds = ids.iter().map(|id| quote! { #message_kind_ident::#id });
+            quote! {
+              #pattern => &[#(#kinds),*],
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let next_state_arms = transitions
+        .transitions
+        .iter()
+        .map(|t| {
+            let pattern = args.state_pattern(&t.start, quote! { _ });
+            let id = &t.name;
+            if t.end.len() == 1 {
+                let end_state = &t.end[0];
+                quote! {
+                  (#pattern, #message_enum_ident::#id(_)) => Some(#kind_ident::#end_state),
+                }
+            } else {
+                quote! {
+                  (#pattern, #message_enum_ident::#id(_)) => None,
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // deferred messages leave the machine in the same state
+    let defer_arms = transitions
+        .defers
+        .iter()
+        .map(|d| {
+            let pattern = args.state_pattern(&d.start, quote! { _ });
+            let id = &d.name;
+            quote! {
+              (#pattern, #message_enum_ident::#id(_)) => true,
+            }
+        })
+        .collect::<Vec<_>>();
+    let next_state_defer_arms = transitions
+        .defers
+        .iter()
+        .map(|d| {
+            let start = &d.start;
+            let pattern = args.state_pattern(start, quote! { _ });
+            let id = &d.name;
+            quote! {
+              (#pattern, #message_enum_ident::#id(_)) => Some(#kind_ident::#start),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let defers = if args.queue.is_some() {
+        quote! {
+          #[allow(dead_code)]
+          #vis fn defers #type_arg_toks(&self, input: &#message_enum_ident #type_arg_toks) -> bool {
+            match (self, input) {
+              #(#defer_arms)*
+              _ => false,
+            }
+          }
+        }
+    } else {
+        quote! {}
+    };
+
+    let unhandled_kind = match args.error {
+        Some(ref error) => quote! { Some(#kind_ident::#error) },
+        None => quote! { None },
+    };
+
+    let queries = quote! {
+      #[allow(dead_code)]
+      #vis fn can_handle #type_arg_toks(&self, input: &#message_enum_ident #type_arg_toks) -> bool {
+        match (self, input) {
+          #(#can_handle_arms)*
+          _ => false,
+        }
+      }
+
+      #[allow(dead_code)]
+      #vis fn accepted_messages(&self) -> &'static [#message_kind_ident] {
+        match self {
+          #(#accepted_arms)*
+          _ => &[],
+        }
+      }
+
+      #[allow(dead_code)]
+      #vis fn next_state_kind #type_arg_toks(&self, input: &#message_enum_ident #type_arg_toks) -> Option<#kind_ident> {
+        match (self, input) {
+          #(#next_state_arms)*
+          #(#next_state_defer_arms)*
+          _ => #unhandled_kind,
+        }
+      }
+
+      #defers
+    };
+
+    // observer notified of every transition executed with `execute_with`,
+    // which keeps a copy of the message unless it is borrowed
+    let observer_ident = Ident::new(
+        &format!("{}Observer", &machine_name.to_string()),
+        Span::call_site(),
+    );
+    let observable = args.by_ref || match transitions.options.derive {
+        Some(ref derive) => derive.iter().any(|path| path.is_ident("Clone")),
+        None => true,
+    };
+
+    let toks = quote! {
+      #[allow(dead_code)]
+      #vis trait #observer_ident #type_arg_toks {
+        fn on_transition(&mut self, _from: #kind_ident, _message: &#message_enum_ident #type_arg_toks, _to: #kind_ident) {}
+
+        fn on_rejected(&mut self, _from: #kind_ident, _message: &#message_enum_ident #type_arg_toks) {}
+      }
+    };
+
+    if observable {
+        stream.extend(proc_macro::TokenStream::from(toks));
+    }
+
+    let observer_generics = quote! {
+      < #(#type_arguments,)* O: #observer_ident #type_arg_toks >
+    };
+
+    // the handlers consume the message, the observer gets a copy of it, or
+    // the borrowed message with the `by_ref` option
+    let (keep_message, clone_bound) = if args.by_ref {
+        (quote! { input }, quote! {})
+    } else {
+        (quote! { input.clone() }, quote! { where #message_enum_ident #type_arg_toks: Clone })
+    };
+    let observed_error = error
+        .iter()
+        .map(|error| {
+            quote! {
+              _ => {
+                observer.on_rejected(from, #observed_message);
+                #error
+              }
+            }
+        })
+        .collect::<Vec<_>>();
+    let observed_matches = messages
+        .iter()
+        .map(|(id, _, moves)| {
+            let next = message_match(id, moves, true);
+            quote! {
+              #message_enum_ident::#id(input) => {
+                #next
+              }
+            }
+        })
+        .collect::<Vec<_>>();
+    let result = args.result();
+    let execute_with = if observable {
+        quote! {
+          #[allow(dead_code)]
+          #vis fn execute_with #observer_generics(self, observer: &mut O, input: #messages_ty #params) -> #ret #clone_bound {
+            let from = self.kind();
+            let message = #keep_message;
+            match input {
+              #(#observed_matches)*
+              #(#observed_error)*
+            }
+          }
+        }
+    } else {
+        quote! {}
+    };
+
+    let toks = quote! {
+      impl #machine_name {
+        #(#functions)*
+
+        #execute
+
+        #execute_with
+
+        #queries
+      }
+    };
+
+    stream.extend(proc_macro::TokenStream::from(toks));
+
+    if transitions.options.metrics || !transitions.timeouts.is_empty() {
+        stream.extend(proc_macro::TokenStream::from(impl_clock(vis, &machine_name)));
+    }
+
+    if transitions.options.metrics {
+        stream.extend(proc_macro::TokenStream::from(impl_metrics(&machine_name, type_arguments, &args)));
+    }
+
+    if !transitions.timeouts.is_empty() {
+        stream.extend(proc_macro::TokenStream::from(impl_timeouts(
+            &machine_name,
+            &transitions.timeouts,
+            type_arguments,
+            &args,
+        )));
+    }
+
+    if args.context.is_some() {
+        let holder_ident = machine_ident(&machine_name, "Machine");
+        let queue_param = args.queue_param();
+        let queue_forward = args.queue_forward();
+        let execute_ret = args.output_ret();
+        let execute_value = args.output_value();
+
+        let toks = quote! {
+          #[allow(dead_code)]
+          impl #holder_ident {
+            #vis fn execute #type_arg_toks(&mut self, input: #messages_ty #queue_param) #execute_ret {
+              let state = self.state.take().expect("the state is only taken during a transition");
+              let #result = state.execute(input, &mut self.context #queue_forward);
+              self.state = Some(next);
+              #execute_value
+            }
+          }
+        };
+
+        stream.extend(proc_macro::TokenStream::from(toks));
+    }
+
+    if transitions.options.runner {
+        stream.extend(proc_macro::TokenStream::from(impl_runner(&machine_name, type_arguments, &args)));
+    }
+
+    //println!("generated: {:?}", gen);
+    trace!("generated transitions: {}", stream);
+    let _ = create_dir("target/machine");
+    let file_name = format!("target/machine/{}.rs", machine_name.to_string().to_lowercase());
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&file_name)
+        .and_then(|mut file| {
+            file.seek(std::io::SeekFrom::End(0))?;
+            file.write_all(stream.to_string().as_bytes())?;
+            file.flush()
+        })
+        .expect("error writing transitions");
+
+    stream
+}
+
+/// merges the groups of rows declared by `transitions!(Name, group = name, [...])`
+/// into a single `transitions!` call
+///
+/// ```rust,ignore
+/// transitions_finish!(TrafficLight, metrics, [basic, #[cfg(feature = "night")] night]);
+/// ```
+#[proc_macro]
+pub fn transitions_finish(input: proc_macro::TokenStream) -> syn::export::TokenStream {
+    let finish = parse_macro_input!(input as TransitionsFinish);
+    trace!("\nparsed transitions_finish: {:#?}", finish);
+
+    let vis = &finish.vis;
+    let machine_name = &finish.machine_name;
+    let options = &finish.options;
+
+    // groups behind a cfg are merged in every combination, each bit of the
+    // mask enabling one of them
+    let optional = finish.groups.iter().filter_map(|(cfg, _)| cfg.as_ref()).collect::<Vec<_>>();
+    let mut stream = TokenStream2::new();
+    for mask in 0..(1usize << optional.len()) {
+        let mut index = 0;
+        let mut macros = Vec::new();
+        let mut predicates = Vec::new();
+        for (cfg, group) in finish.groups.iter() {
+            let enabled = match *cfg {
+                None => true,
+                Some(ref cfg) => {
+                    let enabled = mask & (1 << index) != 0;
+                    index += 1;
+                    predicates.push(if enabled { quote! { #cfg } } else { quote! { not(#cfg) } });
+                    enabled
+                }
+            };
+            if enabled {
+                macros.push(group_macro_ident(machine_name, group));
+            }
+        }
+
+        let (first, rest) = match macros.split_first() {
+            Some((first, rest)) => (first, rest),
+            None => continue,
+        };
+
+        let cfg = if optional.is_empty() {
+            quote! {}
+        } else {
+            quote! { #[cfg(all(#(#predicates),*))] }
+        };
+
+        stream.extend(quote! {
+          #cfg
+          #first!((#vis) #machine_name, (#options), [#(#rest),*], []);
+        });
+    }
+
+    proc_macro::TokenStream::from(stream)
+}
+
+#[derive(Debug)]
+struct TransitionsFinish {
+    pub vis: Visibility,
+    pub machine_name: Ident,
+    /// the options, forwarded as written to `transitions!`
+    pub options: TokenStream2,
+    pub groups: Vec<(Option<NestedMeta>, Ident)>,
+}
+
+impl Parse for TransitionsFinish {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let vis: Visibility = input.parse()?;
+        let machine_name: Ident = input.parse()?;
+        let _: Token![,] = input.parse()?;
+
+        let mut options = TokenStream2::new();
+        while !input.peek(syn::token::Bracket) {
+            input.step(|cursor| match cursor.token_tree() {
+                Some((tt, next)) => {
+                    options.extend(Some(tt));
+                    Ok(((), next))
+                }
+                None => Err(cursor.error("expected the list of groups")),
+            })?;
+        }
+
+        let content;
+        bracketed!(content in input);
+        let mut groups = Vec::new();
+        while !content.is_empty() {
+            let attrs = content.call(Attribute::parse_outer)?;
+            let mut cfg = None;
+            for attr in attrs {
+                match attr.parse_meta()? {
+                    Meta::List(ref list) if list.ident == "cfg" && list.nested.len() == 1 && cfg.is_none() => {
+                        cfg = list.nested.first().map(|pair| (*pair.value()).clone());
+                    }
+                    _ => return Err(syn::Error::new_spanned(attr, "groups only accept a single `#[cfg(..)]` attribute")),
+                }
+            }
+
+            let group: Ident = content.parse()?;
+            if groups.iter().any(|(_, other)| *other == group) {
+                return Err(syn::Error::new(group.span(), format!("group `{}` is listed twice", group)));
+            }
+            groups.push((cfg, group));
+
+            if content.is_empty() {
+                break;
+            }
+            let _: Token![,] = content.parse()?;
+        }
+
+        if groups.is_empty() {
+            return Err(input.error("expected at least one group"));
+        }
+        if input.peek(Token![,]) {
+            let _: Token![,] = input.parse()?;
+        }
+
+        Ok(TransitionsFinish {
+            vis,
+            machine_name,
+            options,
+            groups,
+        })
+    }
+}
+
+// the macro carrying a group's rows: it appends them to the rows gathered so far,
+// then passes them to the next group, the last one calls `transitions!`
+fn impl_group(machine_name: &Ident, group: &Ident, rows: &TokenStream2) -> TokenStream2 {
+    let macro_ident = group_macro_ident(machine_name, group);
+
+    quote! {
+      macro_rules! #macro_ident {
+        (($($vis:tt)*) $name:ident, ($($options:tt)*), [$next:ident $(, $rest:ident)*], [$($rows:tt)*]) => {
+          $next!(($($vis)*) $name, ($($options)*), [$($rest),*], [$($rows)* #rows]);
+        };
+        (($($vis:tt)*) $name:ident, ($($options:tt)*), [], [$($rows:tt)*]) => {
+          transitions!($($vis)* $name, $($options)* [$($rows)* #rows]);
+        };
+      }
+    }
+}
+
+fn group_macro_ident(machine_name: &Ident, group: &Ident) -> Ident {
+    Ident::new(
+        &format!("{}_transitions_{}", machine_name.to_string().to_snake(), group),
+        Span::call_site(),
+    )
+}
+
+// the macro defined by `machine!`, giving its defaults to `transitions!` and `methods!`
+fn defaults_macro_ident(machine_name: &Ident) -> Ident {
+    Ident::new(
+        &format!("{}_machine_defaults", machine_name.to_string().to_snake()),
+        machine_name.span(),
+    )
+}
+
+// the name of a hidden constant of the machine, recording one of its settings
+fn setting_ident(setting: &str, span: Span) -> Ident {
+    Ident::new(&format!("__machine_{}", setting), span)
+}
+
+fn error_setting(error: &Option<Ident>) -> String {
+    match *error {
+        Some(ref error) => format!("error_state_is_{}", error),
+        None => "without_error_state".to_string(),
+    }
+}
+
+fn namespace_setting(namespace: bool) -> String {
+    if namespace { "states_in_module" } else { "states_in_scope" }.to_string()
+}
+
+fn context_setting(context: bool) -> String {
+    if context { "with_context" } else { "without_context" }.to_string()
+}
+
+// the settings are passed to the macro `machine!` defines, and recorded as
+// constants of the machine, which calls without `inherit` check
+fn impl_defaults(vis: &TokenStream2, machine_name: &Ident, options: &MachineOptions) -> TokenStream2 {
+    let macro_ident = defaults_macro_ident(machine_name);
+    let error = &options.error_ident();
+    let settings = [
+        error_setting(error),
+        namespace_setting(options.namespace),
+        context_setting(options.context.is_some()),
+    ]
+    .iter()
+    .map(|setting| setting_ident(setting, Span::call_site()))
+    .collect::<Vec<_>>();
+
+    let mut args = vec![match *error {
+        Some(ref error) => quote! { error = #error },
+        None => quote! { no_error },
+    }];
+    if options.namespace {
+        args.push(quote! { namespace });
+    }
+    if options.context.is_some() {
+        args.push(quote! { context });
+    }
+
+    quote! {
+      #[allow(unused_macros)]
+      macro_rules! #macro_ident {
+        ($target:ident!($($args:tt)*)) => {
+          $target!([#vis #(, #args)*] $($args)*);
+        };
+      }
+
+      #[allow(dead_code, non_upper_case_globals)]
+      impl #machine_name {
+        #(
+          #[doc(hidden)]
+          pub const #settings: () = ();
+        )*
+      }
+    }
+}
+
+// a setting that differs from the machine's one names a missing constant
+fn impl_defaults_check(machine_name: &Ident, settings: &[(String, Span)]) -> TokenStream2 {
+    let checks = settings.iter().map(|(setting, span)| {
+        let setting = setting_ident(setting, *span);
+        quote_spanned! {*span=>
+          const _: () = #machine_name::#setting;
+        }
+    });
+
+    quote! {
+      #(#checks)*
+    }
+}
+
+// calls the macro again through the one defined by `machine!`, which must be
+// in scope: declared before, in the same module or a parent one
+fn impl_defaults_lookup(machine_name: &Ident, macro_name: &str, input: TokenStream2) -> TokenStream2 {
+    let macro_ident = defaults_macro_ident(machine_name);
+    let target = Ident::new(macro_name, Span::call_site());
+
+    quote! {
+      #macro_ident!(#target!(#input));
+    }
+}
+
+/// generated items are public unless a visibility is written
+fn item_vis(vis: &Visibility) -> TokenStream2 {
+    match *vis {
+        Visibility::Inherited => quote! { pub },
+        ref vis => quote! { #vis },
+    }
+}
+
+fn error_ident(error: &Option<Ident>, no_error: bool) -> Option<Ident> {
+    if no_error {
+        None
+    } else {
+        Some(error.clone().unwrap_or_else(|| Ident::new("Error", Span::call_site())))
+    }
+}
+
+/// the module holding the state structs, with the `namespace` option
+fn namespace_ident(machine_name: &Ident, namespace: bool) -> Option<Ident> {
+    if namespace {
+        Some(Ident::new(&machine_name.to_string().to_snake(), Span::call_site()))
+    } else {
+        None
+    }
+}
+
+fn state_path(namespace: &Option<Ident>, state: &Ident) -> TokenStream2 {
+    match namespace {
+        Some(namespace) => quote! { #namespace::#state },
+        None => quote! { #state },
+    }
+}
+
+fn cfg_attributes(attrs: &[Attribute]) -> Vec<&Attribute> {
+    attrs.iter().filter(|attr| attr.path.is_ident("cfg")).collect()
+}
+
+fn machine_ident(machine_name: &Ident, suffix: &str) -> Ident {
+    Ident::new(&format!("{}{}", machine_name, suffix), Span::call_site())
+}
+
+/// the arguments passed to the handlers besides the message, depending on
+/// the options of `transitions!`
+struct HandlerArgs {
+    pub machine_name: Ident,
+    /// visibility of the generated items
+    pub vis: TokenStream2,
+    /// the module holding the state structs, with the `namespace` option
+    pub namespace: Option<Ident>,
+    /// the state reached by rejected messages, none with the `no_error` option
+    pub error: Option<Ident>,
+    /// the context type, with the `context` option
+    pub context: Option<Ident>,
+    /// the queue type, with the `runner` option
+    pub queue: Option<TokenStream2>,
+    /// the command type, with the `output` option
+    pub output: Option<Type>,
+    /// messages are borrowed, with the `by_ref` option
+    pub by_ref: bool,
+    /// handlers are trait methods, with the `traits` option
+    pub traits: bool,
+}
+
+impl HandlerArgs {
+    fn new(
+        vis: &TokenStream2,
+        machine_name: &Ident,
+        error: Option<Ident>,
+        options: &TransitionsOptions,
+        type_arg_toks: &TokenStream2,
+    ) -> Self {
+        let queue_ident = machine_ident(machine_name, "Queue");
+
+        HandlerArgs {
+            machine_name: machine_name.clone(),
+            vis: vis.clone(),
+            namespace: namespace_ident(machine_name, options.namespace),
+            error,
+            context: if options.context {
+                Some(machine_ident(machine_name, "Context"))
+            } else {
+                None
+            },
+            queue: if options.runner {
+                Some(quote! { #queue_ident #type_arg_toks })
+            } else {
+                None
+            },
+            output: options.output.clone(),
+            by_ref: options.by_ref,
+            traits: options.traits,
+        }
+    }
+
+    /// calls the handler of the `start` state bound to `state`. Inline handlers
+    /// are inherent methods, the others are called through their trait if any,
+    /// so a missing one is reported as an unimplemented trait item
+    fn call(&self, start: &Ident, fn_ident: &Ident, inline: bool, input: Option<TokenStream2>) -> TokenStream2 {
+        let mut args = input.into_iter().collect::<Vec<_>>();
+        args.extend(self.handler_args());
+
+        if self.traits && !inline {
+            let trait_ident = self.state_item_ident(start, "Transitions");
+            let start = self.state(start);
+            quote! { <#start as #trait_ident>::#fn_ident(state #(, #args)*) }
+        } else {
+            quote! { state.#fn_ident(#(#args),*) }
+        }
+    }
+
+    /// names an item generated for a state. Namespaced machines can share state
+    /// names, so the machine's name comes first
+    fn state_item_ident(&self, state: &Ident, suffix: &str) -> Ident {
+        match self.namespace {
+            Some(_) => Ident::new(&format!("{}{}{}", self.machine_name, state, suffix), Span::call_site()),
+            None => machine_ident(state, suffix),
+        }
+    }
+
+    /// the enum returned by a handler with multiple end states
+    fn outcome_ident(&self, start: &Ident, message: &Ident) -> Ident {
+        self.state_item_ident(start, &format!("{}Outcome", message))
+    }
+
+    /// the struct of a state. The error state has no struct in the machine,
+    /// its handlers are implemented on the `<Name><Error>State` marker
+    fn state(&self, state: &Ident) -> TokenStream2 {
+        match self.error_marker(state) {
+            Some(marker) => quote! { #marker },
+            None => state_path(&self.namespace, state),
+        }
+    }
+
+    fn error_marker(&self, state: &Ident) -> Option<Ident> {
+        match self.error {
+            Some(ref error) if error == state => Some(machine_ident(&self.machine_name, &format!("{}State", error))),
+            _ => None,
+        }
+    }
+
+    /// matches the machine in the `state` state, binding its struct with `binding`
+    fn state_pattern(&self, state: &Ident, binding: TokenStream2) -> TokenStream2 {
+        let machine_name = &self.machine_name;
+        match self.error_marker(state) {
+            Some(_) => quote! { #machine_name::#state },
+            None => quote! { #machine_name::#state(#binding) },
+        }
+    }
+
+    /// binds `state` in an arm matched by `state_pattern`, for the error state
+    fn bind_state(&self, state: &Ident) -> TokenStream2 {
+        match self.error_marker(state) {
+            Some(marker) => quote! { let state = #marker; },
+            None => quote! {},
+        }
+    }
+
+    /// wraps the struct of the `state` state in the machine
+    fn state_value(&self, state: &Ident, value: TokenStream2) -> TokenStream2 {
+        let machine_name = &self.machine_name;
+        match self.error_marker(state) {
+            Some(marker) => quote! {{ let _: #marker = #value; #machine_name::#state }},
+            None => quote! { #machine_name::#state(#value) },
+        }
+    }
+
+    /// the type of the `input` parameter, for a message or the messages enum
+    fn input(&self, message: TokenStream2) -> TokenStream2 {
+        if self.by_ref {
+            quote! { &#message }
+        } else {
+            message
+        }
+    }
+
+    /// passes an owned message to a method taking `input`
+    fn pass(&self, input: TokenStream2) -> TokenStream2 {
+        if self.by_ref {
+            quote! { &#input }
+        } else {
+            input
+        }
+    }
+
+    /// parameters of the generated methods, supplied by their caller
+    fn params(&self) -> TokenStream2 {
+        let context = self.context_param();
+        let queue = self.queue_param();
+        quote! { #context #queue }
+    }
+
+    fn context_param(&self) -> TokenStream2 {
+        match self.context {
+            Some(ref context) => quote! { , context: &mut #context },
+            None => quote! {},
+        }
+    }
+
+    fn queue_param(&self) -> TokenStream2 {
+        match self.queue {
+            Some(ref queue) => quote! { , queue: &mut #queue },
+            None => quote! {},
+        }
+    }
+
+    /// passes the parameters on to another generated method
+    fn forward(&self) -> TokenStream2 {
+        let context = self.context_forward();
+        let queue = self.queue_forward();
+        quote! { #context #queue }
+    }
+
+    fn context_forward(&self) -> TokenStream2 {
+        match self.context {
+            Some(_) => quote! { , context },
+            None => quote! {},
+        }
+    }
+
+    fn queue_forward(&self) -> TokenStream2 {
+        match self.queue {
+            Some(_) => quote! { , queue },
+            None => quote! {},
+        }
+    }
+
+    fn handler_args(&self) -> Vec<TokenStream2> {
+        let mut args = Vec::new();
+        if self.context.is_some() {
+            args.push(quote! { context });
+        }
+        if self.output.is_some() {
+            args.push(quote! { &mut output });
+        }
+        if self.queue.is_some() {
+            args.push(quote! { queue });
+        }
+        args
+    }
+
+    /// parameters of the handlers generated from inline bodies
+    fn handler_params(&self) -> Vec<(Ident, TokenStream2)> {
+        let mut params = Vec::new();
+        if let Some(ref context) = self.context {
+            params.push((Ident::new("context", Span::call_site()), quote! { &mut #context }));
+        }
+        if let Some(ref output) = self.output {
+            params.push((Ident::new("output", Span::call_site()), quote! { &mut Vec<#output> }));
+        }
+        if let Some(ref queue) = self.queue {
+            params.push((Ident::new("queue", Span::call_site()), quote! { &mut #queue }));
+        }
+        params
+    }
+
+    /// the type returned by the generated methods calling the handlers
+    fn ret(&self, machine_name: &Ident) -> TokenStream2 {
+        match self.output {
+            Some(ref output) => quote! { (#machine_name, Vec<#output>) },
+            None => quote! { #machine_name },
+        }
+    }
+
+    /// declares the buffer the handlers called in `next` write to
+    fn collect(&self, next: TokenStream2) -> TokenStream2 {
+        if self.output.is_some() {
+            quote! {
+              let mut output = Vec::new();
+              let next = #next;
+              (next, output)
+            }
+        } else {
+            next
+        }
+    }
+
+    fn without_output(&self, next: TokenStream2) -> TokenStream2 {
+        if self.output.is_some() {
+            quote! { (#next, Vec::new()) }
+        } else {
+            next
+        }
+    }
+
+    /// return type of the wrappers' `execute`, which keep the machine
+    fn output_ret(&self) -> TokenStream2 {
+        match self.output {
+            Some(ref output) => quote! { -> Vec<#output> },
+            None => quote! {},
+        }
+    }
+
+    fn output_value(&self) -> TokenStream2 {
+        if self.output.is_some() {
+            quote! { output }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// binds the value returned by a generated method to `next` and `output`
+    fn result(&self) -> TokenStream2 {
+        if self.output.is_some() {
+            quote! { (next, output) }
+        } else {
+            quote! { next }
+        }
+    }
+}
+
+fn type_arguments_toks(type_arguments: &[GenericArgument]) -> TokenStream2 {
+    if type_arguments.is_empty() {
+        quote! {}
+    } else {
+        quote! { < #(#type_arguments),* > }
+    }
+}
+
+/// the signature of a handler, as called by the generated methods
+struct HandlerSignature {
+    pub fn_ident: Ident,
+    pub generics: TokenStream2,
+    /// the message type, absent for timeouts
+    pub input: Option<TokenStream2>,
+    pub ret: TokenStream2,
+}
+
+impl HandlerSignature {
+    fn transition(t: &Transition, args: &HandlerArgs, type_arg_toks: &TokenStream2) -> Self {
+        // the queue is parameterized by the type arguments of every message
+        let generics = if args.queue.is_some() {
+            type_arg_toks.clone()
+        } else {
+            type_arguments_toks(&reorder_type_arguments(type_args(&t.message)))
+        };
+        let message = &t.message;
+        let ret = if t.end.len() == 1 {
+            args.state(&t.end[0])
+        } else {
+            let outcome_ident = args.outcome_ident(&t.start, &t.name);
+            quote! { #outcome_ident }
+        };
+
+        HandlerSignature {
+            fn_ident: Ident::new(&format!("on_{}", t.name.to_string().to_snake()), Span::call_site()),
+            generics,
+            input: Some(args.input(quote! { #message })),
+            ret,
+        }
+    }
+
+    fn timeout(t: &Timeout, args: &HandlerArgs, type_arg_toks: &TokenStream2) -> Self {
+        let generics = if args.queue.is_some() {
+            type_arg_toks.clone()
+        } else {
+            quote! {}
+        };
+        let ret = if t.end.len() == 1 {
+            args.state(&t.end[0])
+        } else {
+            let outcome_ident = args.state_item_ident(&t.start, "TimeoutOutcome");
+            quote! { #outcome_ident }
+        };
+
+        HandlerSignature {
+            fn_ident: Ident::new("on_timeout", Span::call_site()),
+            generics,
+            input: None,
+            ret,
+        }
+    }
+
+    fn to_tokens(&self, args: &HandlerArgs) -> TokenStream2 {
+        self.to_tokens_binding(args, None)
+    }
+
+    /// the signature of an inline handler whose closure binds `bound` parameters,
+    /// `self` included. The parameters it leaves out are prefixed with `_`
+    fn to_tokens_binding(&self, args: &HandlerArgs, bound: Option<usize>) -> TokenStream2 {
+        let HandlerSignature { fn_ident, generics, input, ret } = self;
+        let params = input
+            .iter()
+            .map(|ty| (Ident::new("input", Span::call_site()), ty.clone()))
+            .chain(args.handler_params())
+            .enumerate()
+            .map(|(index, (name, ty))| {
+                let name = match bound {
+                    Some(bound) if index + 1 >= bound => Ident::new(&format!("_{}", name), name.span()),
+                    _ => name,
+                };
+                quote! { , #name: #ty }
+            })
+            .collect::<Vec<_>>();
+
+        quote! {
+          fn #fn_ident #generics(self #(#params)*) -> #ret
+        }
+    }
+}
+
+/// a handler written inline in a row, binding the closure parameters to the
+/// state, the message if any, and the handler arguments
+fn impl_inline_handler(start: &Ident, signature: &HandlerSignature, body: &ExprClosure, args: &HandlerArgs) -> TokenStream2 {
+    let vis = &args.vis;
+    let mut values = vec![quote! { self }];
+    if signature.input.is_some() {
+        values.push(quote! { input });
+    }
+    values.extend(args.handler_params().into_iter().map(|(name, _)| quote! { #name }));
+
+    // bound at once, a parameter cannot shadow a value bound after it
+    let (patterns, types): (Vec<_>, Vec<_>) = body
+        .inputs
+        .iter()
+        .map(|arg| match *arg {
+            FnArg::Captured(ref arg) => {
+                let (pat, ty) = (&arg.pat, &arg.ty);
+                (quote! { #pat }, quote! { #ty })
+            }
+            ref arg => (quote! { #arg }, quote! { _ }),
+        })
+        .unzip();
+    let values = &values[..patterns.len()];
+    let expr = &body.body;
+    let signature = signature.to_tokens_binding(args, Some(body.inputs.len()));
+    let start = args.state(start);
+
+    quote! {
+      impl #start {
+        #vis #signature {
+          let (#(#patterns,)*): (#(#types,)*) = (#(#values,)*);
+          #expr
+        }
+      }
+    }
+}
+
+/// the clock used by the generated wrappers, implemented for closures
+/// so tests can drive time manually
+fn impl_clock(vis: &TokenStream2, machine_name: &Ident) -> TokenStream2 {
+    let clock_ident = machine_ident(machine_name, "Clock");
+    let manual_clock_ident = machine_ident(machine_name, "ManualClock");
+
+    quote! {
+      #[allow(dead_code)]
+      #vis trait #clock_ident {
+        fn now(&self) -> std::time::Instant;
+      }
+
+      impl<F: Fn() -> std::time::Instant> #clock_ident for F {
+        fn now(&self) -> std::time::Instant {
+          self()
+        }
+      }
+
+      /// a clock that only moves when told to, its clones share the same time
+      #[derive(Clone, Debug)]
+      #[allow(dead_code)]
+      #vis struct #manual_clock_ident {
+        now: std::rc::Rc<std::cell::Cell<std::time::Instant>>,
+      }
+
+      #[allow(dead_code)]
+      impl #manual_clock_ident {
+        #vis fn new(now: std::time::Instant) -> Self {
+          #manual_clock_ident {
+            now: std::rc::Rc::new(std::cell::Cell::new(now)),
+          }
+        }
+
+        #vis fn set(&self, now: std::time::Instant) {
+          self.now.set(now);
+        }
+
+        #vis fn advance(&self, duration: std::time::Duration) {
+          self.now.set(self.now.get() + duration);
+        }
+      }
+
+      impl #clock_ident for #manual_clock_ident {
+        fn now(&self) -> std::time::Instant {
+          self.now.get()
+        }
+      }
+    }
+}
+
+/// timeout queries and handlers on the machine, and a wrapper tracking
+/// when the current state was entered to fire them
+fn impl_timeouts(
+    machine_name: &Ident,
+    timeouts: &[Timeout],
+    type_arguments: &[GenericArgument],
+    args: &HandlerArgs,
+) -> TokenStream2 {
+    let vis = &args.vis;
+    let message_enum_ident = machine_ident(machine_name, "Messages");
+    let clock_ident = machine_ident(machine_name, "Clock");
+    let timers_ident = machine_ident(machine_name, "Timers");
+    let type_arg_toks = type_arguments_toks(type_arguments);
+    let messages_ty = args.input(quote! { #message_enum_ident #type_arg_toks });
+    let params = args.params();
+    let forward = args.forward();
+    let ret = args.ret(machine_name);
+    let result = args.result();
+
+    // the queue is parameterized by the type arguments of every message
+    let timeout_generics = if args.queue.is_some() {
+        type_arg_toks.clone()
+    } else {
+        quote! {}
+    };
+
+    let execute_ret = args.output_ret();
+    let execute_value = args.output_value();
+    // the wrapper returns the commands emitted by the handlers
+    let (poll_ret, poll_init, poll_extend, poll_value) = match args.output {
+        Some(ref output) => (
+            quote! { (bool, Vec<#output>) },
+            quote! { let mut commands = Vec::new(); },
+            quote! { commands.extend(output); },
+            quote! { (fired, commands) },
+        ),
+        None => (quote! { bool }, quote! {}, quote! {}, quote! { fired }),
+    };
+
+    let timeout_arms = timeouts
+        .iter()
+        .map(|t| {
+            let start = &t.start;
+            let duration = &t.duration;
+            let pattern = args.state_pattern(start, quote! { _ });
+            quote! { #pattern => Some(#duration), }
+        })
+        .collect::<Vec<_>>();
+
+    let on_timeout_ident = Ident::new("on_timeout", Span::call_site());
+    let on_timeout_arms = timeouts
+        .iter()
+        .map(|t| {
+            let start = &t.start;
+            let call = args.call(start, &on_timeout_ident, t.body.is_some(), None);
+            let next = if t.end.len() == 1 {
+                args.state_value(&t.end[0], call)
+            } else {
+                quote! { #machine_name::from(#call) }
+            };
+            let next = log_transition(machine_name, start, "timeout", next);
+            let pattern = args.state_pattern(start, quote! { state });
+            let bind = args.bind_state(start);
+
+            quote! { #pattern => { #bind #next } }
+        })
+        .collect::<Vec<_>>();
+    // without an error state, the states without a timeout stay as they are
+    let rejected = match args.error {
+        Some(ref error) => {
+            let rejected = log_rejected(machine_name, error, "timeout");
+            quote! { _ => #rejected, }
+        }
+        None => quote! { state => state, },
+    };
+    let on_timeout = args.collect(quote! {
+      match self {
+        #(#on_timeout_arms)*
+        #rejected
+      }
+    });
+
+    let outcomes = timeouts.iter().filter(|t| t.end.len() > 1).map(|t| {
+        let outcome_ident = args.state_item_ident(&t.start, "TimeoutOutcome");
+        impl_outcome(args, machine_name, &outcome_ident, &t.end)
+    });
+
+    let handlers = timeouts
+        .iter()
+        .filter_map(|t| {
+            t.body.as_ref().map(|body| {
+                let signature = HandlerSignature::timeout(t, args, &type_arg_toks);
+                impl_inline_handler(&t.start, &signature, body, args)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    quote! {
+      #(#outcomes)*
+
+      #(#handlers)*
+
+      #[allow(dead_code)]
+      impl #machine_name {
+        #vis fn timeout(&self) -> Option<std::time::Duration> {
+          match self {
+            #(#timeout_arms)*
+            _ => None,
+          }
+        }
+
+        #vis fn on_timeout #timeout_generics(self #params) -> #ret {
+          #on_timeout
+        }
+      }
+
+      #[allow(dead_code)]
+      #vis struct #timers_ident<C> {
+        machine: Option<#machine_name>,
+        clock: C,
+        entered: std::time::Instant,
+      }
 
-extern crate case;
-extern crate proc_macro;
-/*
-#[macro_use] mod dynamic_machine;
-
-#[macro_export]
-macro_rules! machine(
-  ( $($token:tt)* ) => ( static_machine!( $($token)* ); );
-);
-*/
-
-#[macro_use]
-extern crate log;
-#[macro_use]
-extern crate syn;
-#[macro_use]
-extern crate quote;
-
-use std::collections::{HashMap, HashSet};
-use std::fs::{File, OpenOptions, create_dir};
-use std::io::{Seek, Write};
-
-use case::CaseExt;
-use syn::export::Span;
-use syn::punctuated::Pair;
-use syn::parse::{Parse, ParseStream, Result};
-use syn::{
-    Abi, Attribute, Expr, FnArg, FnDecl, Generics, Ident, ItemEnum, MethodSig, ReturnType, Type,
-    WhereClause, PathArguments, GenericArgument,
-};
-use quote::ToTokens;
-
-struct Machine {
-    attributes: Vec<Attribute>,
-    data: ItemEnum,
-}
-
-impl Parse for Machine {
-    fn parse(input: ParseStream) -> Result<Self> {
-        let attributes: Vec<Attribute> = input.call(Attribute::parse_outer)?;
-        let data: syn::ItemEnum = input.parse()?;
-
-        Ok(Machine { attributes, data })
-    }
-}
-
-#[proc_macro]
-pub fn machine(input: proc_macro::TokenStream) -> syn::export::TokenStream {
-    let ast = parse_macro_input!(input as Machine);
-
-    // Build the impl
-    let (name, gen) = impl_machine(&ast);
-
-    trace!("generated: {}", gen);
-
-    let file_name = format!("target/machine/{}.rs", name.to_string().to_lowercase());
-    let _ = create_dir("target/machine");
-    File::create(&file_name)
-        .and_then(|mut file| {
-            file.seek(std::io::SeekFrom::End(0))?;
-            file.write_all(gen.to_string().as_bytes())?;
-            file.flush()
-        })
-        .expect("error writing machine definition");
-
-    gen
-}
-
-fn impl_machine(m: &Machine) -> (&Ident, syn::export::TokenStream) {
-    let Machine { attributes, data } = m;
-    let ast = data;
-    //println!("attributes: {:?}", attributes);
-    //println!("ast: {:#?}", ast);
-
-    let machine_name = &ast.ident;
-    let variants_names = &ast.variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
-    let structs_names = variants_names.clone();
-
-    // define the state enum
-    let toks = quote! {
-      #(#attributes)*
-      pub enum #machine_name {
-        Error,
-        #(#variants_names(#structs_names)),*
-      }
-    };
-
-    let mut stream = proc_macro::TokenStream::from(toks);
-
-    // define structs for each state
-    for ref variant in ast.variants.iter() {
-        let name = &variant.ident;
-
-        let fields = &variant
-            .fields
-            .iter()
-            .map(|f| {
-                let vis = &f.vis;
-                let ident = &f.ident;
-                let ty = &f.ty;
-
-                quote! {
-                  #vis #ident: #ty
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let toks = quote! {
-          #(#attributes)*
-          pub struct #name {
-            #(#fields),*
-          }
-        };
-
-        stream.extend(proc_macro::TokenStream::from(toks));
-    }
-
-    let methods = &ast
-        .variants
-        .iter()
-        .map(|variant| {
-            let fn_name = Ident::new(&variant.ident.to_string().to_snake(), Span::call_site());
-            let struct_name = &variant.ident;
-
-            let args = &variant
-                .fields
-                .iter()
-                .map(|f| {
-                    let ident = &f.ident;
-                    let ty = &f.ty;
-
-                    quote! {
-                      #ident: #ty
-                    }
-                })
-                .collect::<Vec<_>>();
-
-            let arg_names = &variant.fields.iter().map(|f| &f.ident).collect::<Vec<_>>();
-
-            quote! {
-              pub fn #fn_name(#(#args),*) -> #machine_name {
-                #machine_name::#struct_name(#struct_name {
-                  #(#arg_names),*
-                })
-              }
-            }
-        })
-        .collect::<Vec<_>>();
-
-    let toks = quote! {
-      impl #machine_name {
-        #(#methods)*
-
-        pub fn error() -> #machine_name {
-          #machine_name::Error
-        }
-      }
-    };
-
-    stream.extend(proc_macro::TokenStream::from(toks));
-
-    (machine_name, stream)
-}
-
-#[derive(Debug)]
-struct Transitions {
-    pub machine_name: Ident,
-    pub transitions: Vec<Transition>,
-}
-
-#[derive(Debug)]
-struct Transition {
-    pub start: Ident,
-    pub message: Type,
-    pub end: Vec<Ident>,
-}
-
-impl Parse for Transitions {
-    fn parse(input: ParseStream) -> Result<Self> {
-        let machine_name: Ident = input.parse()?;
-        let _: Token![,] = input.parse()?;
-
-        let content;
-        bracketed!(content in input);
-
-        trace!("content: {:?}", content);
-        let mut transitions = Vec::new();
-
-        let t: Transition = content.parse()?;
-        transitions.push(t);
-
-        loop {
-            let lookahead = content.lookahead1();
-            if lookahead.peek(Token![,]) {
-                let _: Token![,] = content.parse()?;
-                let t: Transition = content.parse()?;
-                transitions.push(t);
-            } else {
-                break;
-            }
-        }
-
-        Ok(Transitions {
-            machine_name,
-            transitions,
-        })
-    }
-}
-
-impl Parse for Transition {
-    fn parse(input: ParseStream) -> Result<Self> {
-        let left;
-        parenthesized!(left in input);
-
-        let start: Ident = left.parse()?;
-        let _: Token![,] = left.parse()?;
-        let message: Type = left.parse()?;
+      #[allow(dead_code)]
+      impl<C: #clock_ident> #timers_ident<C> {
+        #vis fn new(machine: #machine_name, clock: C) -> Self {
+          let entered = clock.now();
+          #timers_ident {
+            machine: Some(machine),
+            clock,
+            entered,
+          }
+        }
 
-        let _: Token![=>] = input.parse()?;
+        #vis fn machine(&self) -> &#machine_name {
+          self.machine.as_ref().expect("the machine is only taken during a transition")
+        }
 
-        let end = match input.parse::<Ident>() {
-            Ok(i) => vec![i],
-            Err(_) => {
-                let content;
-                bracketed!(content in input);
+        #vis fn into_inner(self) -> #machine_name {
+          self.machine.expect("the machine is only taken during a transition")
+        }
 
-                //println!("content: {:?}", content);
-                let mut states = Vec::new();
+        #vis fn execute #type_arg_toks(&mut self, input: #messages_ty #params) #execute_ret {
+          let machine = self.machine.take().expect("the machine is only taken during a transition");
+          let from = machine.kind();
+          let accepted = machine.can_handle(&input);
 
-                let t: Ident = content.parse()?;
-                states.push(t);
+          let #result = machine.execute(input #forward);
+          // an accepted message restarts the timer, even if the state transitions to itself
+          if accepted || next.kind() != from {
+            self.entered = self.clock.now();
+          }
+          self.machine = Some(next);
+          #execute_value
+        }
 
-                loop {
-                    let lookahead = content.lookahead1();
-                    if lookahead.peek(Token![,]) {
-                        let _: Token![,] = content.parse()?;
-                        let t: Ident = content.parse()?;
-                        states.push(t);
-                    } else {
-                        break;
-                    }
-                }
+        /// the instant at which the current state times out
+        #vis fn next_deadline(&self) -> Option<std::time::Instant> {
+          self.machine().timeout().map(|timeout| self.entered + timeout)
+        }
 
-                states
+        /// fires the timeouts expired at `now`, returns true if at least one fired
+        #vis fn poll_timeouts #timeout_generics(&mut self, now: std::time::Instant #params) -> #poll_ret {
+          let mut fired = false;
+          let mut last_deadline = None;
+          #poll_init
+
+          while let Some(deadline) = self.next_deadline() {
+            // a zero timeout gives the same deadline again, it fires once per poll
+            if now < deadline || last_deadline == Some(deadline) {
+              break;
             }
-        };
 
-        Ok(Transition {
-            start,
-            message,
-            end,
-        })
+            let machine = self.machine.take().expect("the machine is only taken during a transition");
+            let #result = #machine_name::on_timeout(machine #forward);
+            #poll_extend
+            self.machine = Some(next);
+            // chained timeouts start from the expired deadline, not from the polling time
+            self.entered = deadline;
+            last_deadline = Some(deadline);
+            fired = true;
+          }
+
+          #poll_value
+        }
+      }
     }
 }
 
-impl Transitions {
-    pub fn render(&self) {
-        let file_name = format!(
-            "target/machine/{}.dot",
-            self.machine_name.to_string().to_lowercase()
-        );
-        let _ = create_dir("target/machine");
-        let mut file = File::create(&file_name).expect("error opening dot file");
-
-        file.write_all(format!("digraph {} {{\n", self.machine_name.to_string()).as_bytes())
-            .expect("error writing to dot file");
+/// wrapper around the machine reporting transitions, rejected messages
+/// and time spent in each state to a metrics sink
+fn impl_metrics(
+    machine_name: &Ident,
+    type_arguments: &[GenericArgument],
+    args: &HandlerArgs,
+) -> TokenStream2 {
+    let vis = &args.vis;
+    let kind_ident = machine_ident(machine_name, "Kind");
+    let message_enum_ident = machine_ident(machine_name, "Messages");
+    let message_kind_ident = machine_ident(machine_name, "MessageKind");
+    let clock_ident = machine_ident(machine_name, "Clock");
+    let sink_ident = machine_ident(machine_name, "MetricsSink");
+    let instrumented_ident = machine_ident(machine_name, "Instrumented");
+    let type_arg_toks = type_arguments_toks(type_arguments);
+    let messages_ty = args.input(quote! { #message_enum_ident #type_arg_toks });
+    // only machines with the `runner` option can defer messages
+    let params = args.params();
+    let forward = args.forward();
+    let result = args.result();
+    let execute_ret = args.output_ret();
+    let execute_value = args.output_value();
+    let deferred = if args.queue.is_none() {
+        quote! { false }
+    } else {
+        quote! { machine.defers(&input) }
+    };
 
-        let mut edges = Vec::new();
-        for transition in self.transitions.iter() {
-            for state in transition.end.iter() {
-                edges.push((&transition.start, &transition.message, state));
-            }
-        }
+    quote! {
+      #[allow(dead_code)]
+      #vis trait #sink_ident {
+        fn transition(&mut self, _from: #kind_ident, _message: #message_kind_ident, _to: #kind_ident) {}
 
-        for edge in edges.iter() {
-            file.write_all(
-                &format!("{} -> {} [ label = \"{}\" ];\n", edge.0, edge.2, edge.1.into_token_stream()).as_bytes(),
-            )
-            .expect("error writing to dot file");
-        }
+        fn rejected(&mut self, _from: #kind_ident, _message: #message_kind_ident) {}
 
-        file.write_all(&b"}"[..])
-            .expect("error writing to dot file");
-        file.flush().expect("error flushhing dot file");
-    }
-}
+        fn dwell(&mut self, _state: #kind_ident, _duration: std::time::Duration) {}
+      }
 
-#[proc_macro]
-pub fn transitions(input: proc_macro::TokenStream) -> syn::export::TokenStream {
-    //println!("\ninput: {:?}", input);
-    let mut stream = proc_macro::TokenStream::new();
+      #[allow(dead_code)]
+      #vis struct #instrumented_ident<S, C> {
+        machine: Option<#machine_name>,
+        sink: S,
+        clock: C,
+        entered: std::time::Instant,
+      }
 
-    let transitions = parse_macro_input!(input as Transitions);
-    trace!("\nparsed transitions: {:#?}", transitions);
+      #[allow(dead_code)]
+      impl<S: #sink_ident, C: #clock_ident> #instrumented_ident<S, C> {
+        #vis fn new(machine: #machine_name, sink: S, clock: C) -> Self {
+          let entered = clock.now();
+          #instrumented_ident {
+            machine: Some(machine),
+            sink,
+            clock,
+            entered,
+          }
+        }
 
-    transitions.render();
+        #vis fn machine(&self) -> &#machine_name {
+          self.machine.as_ref().expect("the machine is only taken during a transition")
+        }
 
-    let machine_name = transitions.machine_name;
+        #vis fn sink(&self) -> &S {
+          &self.sink
+        }
 
-    let mut messages = HashMap::new();
-    for t in transitions.transitions.iter() {
-        let entry = messages.entry(&t.message).or_insert(Vec::new());
-        entry.push((&t.start, &t.end));
-    }
+        #vis fn sink_mut(&mut self) -> &mut S {
+          &mut self.sink
+        }
 
-    //let mut message_types = transitions.transitions.iter().map(|t| &t.message).collect::<Vec<_>>();
+        #vis fn into_inner(self) -> #machine_name {
+          self.machine.expect("the machine is only taken during a transition")
+        }
 
-    let mut type_arguments = HashSet::new();
-    for t in transitions.transitions.iter() {
-      let mut args = type_args(&t.message);
-      type_arguments.extend(args.drain());
-    }
+        #vis fn execute #type_arg_toks(&mut self, input: #messages_ty #params) #execute_ret {
+          let machine = self.machine.take().expect("the machine is only taken during a transition");
+          let from = machine.kind();
+          let message = input.kind();
+          let accepted = machine.can_handle(&input);
+          let deferred = #deferred;
 
-    let type_arguments = reorder_type_arguments(type_arguments);
+          let #result = machine.execute(input #forward);
+          let to = next.kind();
+          self.machine = Some(next);
 
-    // create an enum from the messages
-    let message_enum_ident = Ident::new(
-        &format!("{}Messages", &machine_name.to_string()),
-        Span::call_site(),
-    );
-    let structs_names = messages.keys().collect::<Vec<_>>();
-    let variants_names = structs_names.iter().map(|t| type_last_ident(*t)).collect::<Vec<_>>();
+          if accepted {
+            self.sink.transition(from, message, to);
+          } else if !deferred {
+            self.sink.rejected(from, message);
+          }
 
+          // a state is left on each accepted message, even if it transitions
+          // to itself, or when a rejected message moves it to the error state
+          if accepted || from != to {
+            let now = self.clock.now();
+            self.sink.dwell(from, now.duration_since(self.entered));
+            self.entered = now;
+          }
+          #execute_value
+        }
+      }
+    }
+}
 
-    let type_arg_toks = if type_arguments.is_empty() {
-      quote!{}
+/// the queue handed to the handlers, and a runner processing each message to completion
+fn impl_runner(machine_name: &Ident, type_arguments: &[GenericArgument], args: &HandlerArgs) -> TokenStream2 {
+    let vis = &args.vis;
+    let kind_ident = machine_ident(machine_name, "Kind");
+    let message_enum_ident = machine_ident(machine_name, "Messages");
+    let message_kind_ident = machine_ident(machine_name, "MessageKind");
+    let queue_ident = machine_ident(machine_name, "Queue");
+    let run_error_ident = machine_ident(machine_name, "RunError");
+    let runner_ident = machine_ident(machine_name, "Runner");
+    let type_arg_toks = type_arguments_toks(type_arguments);
+    let result = args.result();
+    let context_param = args.context_param();
+    let context_forward = args.context_forward();
+    let input = args.pass(quote! { input });
+    let log_dropped = if cfg!(feature = "runtime-log") {
+        quote! {
+          extern crate log;
+          for input in dropped.iter() {
+            log::warn!("{}: {} dropped deferred {}", stringify!(#machine_name), machine.kind(), input.kind());
+          }
+        }
     } else {
-      quote!{
-        < #(#type_arguments),* >
-      }
+        quote! {}
+    };
+    // without an error state, every message is accepted
+    let failed = match args.error {
+        Some(ref error) => quote! { next.kind() == #kind_ident::#error },
+        None => quote! { false },
     };
 
-    // define the state enum
-    let toks = quote! {
-      #[derive(Clone,Debug,PartialEq)]
-      pub enum #message_enum_ident #type_arg_toks {
-        #(#variants_names(#structs_names)),*
-      }
+    // the commands emitted by the handlers are kept until taken, even if a message failed
+    let (output_field, output_init, output_extend, take_output) = match args.output {
+        Some(ref output) => (
+            quote! { output: Vec<#output>, },
+            quote! { output: Vec::new(), },
+            quote! { self.output.extend(output); },
+            quote! {
+              /// the commands emitted by the handlers since the last call
+              #vis fn take_output(&mut self) -> Vec<#output> {
+                std::mem::replace(&mut self.output, Vec::new())
+              }
+            },
+        ),
+        None => (quote! {}, quote! {}, quote! {}, quote! {}),
     };
 
-    stream.extend(proc_macro::TokenStream::from(toks));
-    let functions = messages
-      .iter()
-      .map(|(msg, moves)| {
-        let fn_ident = Ident::new(
-          //&format!("on_{}", &msg.to_string().to_snake()),
-          &format!("on_{}", type_to_snake(msg)),
-          Span::call_site(),
-          );
-        let mv = moves.iter().map(|(start, end)| {
-          if end.len() == 1 {
-            let end_state = &end[0];
-            quote!{
-              #machine_name::#start(state) => #machine_name::#end_state(state.#fn_ident(input)),
-            }
-          } else {
-            quote!{
-              #machine_name::#start(state) => state.#fn_ident(input),
-            }
-          }
-        }).collect::<Vec<_>>();
+    quote! {
+      /// messages raised by the handlers, processed in FIFO order by the runner,
+      /// and deferred messages waiting for a state accepting them
+      #[derive(Debug)]
+      #[allow(dead_code)]
+      #vis struct #queue_ident #type_arg_toks {
+        messages: std::collections::VecDeque<#message_enum_ident #type_arg_toks>,
+        deferred: Vec<#message_enum_ident #type_arg_toks>,
+      }
 
-        let type_arguments = reorder_type_arguments(type_args(msg));
-        let type_arg_toks = if type_arguments.is_empty() {
-          quote!{}
-        } else {
-          quote!{
-            < #(#type_arguments),* >
+      #[allow(dead_code)]
+      impl #type_arg_toks #queue_ident #type_arg_toks {
+        #vis fn new() -> Self {
+          #queue_ident {
+            messages: std::collections::VecDeque::new(),
+            deferred: Vec::new(),
           }
-        };
+        }
 
-        quote! {
-          pub fn #fn_ident #type_arg_toks(self, input: #msg) -> #machine_name {
-            match self {
-              #(#mv)*
-              _ => #machine_name::Error,
-            }
-          }
+        #vis fn push(&mut self, input: #message_enum_ident #type_arg_toks) {
+          self.messages.push_back(input);
         }
-      })
-    .collect::<Vec<_>>();
 
-    let matches = messages
-      .keys()
-      .map(|msg| {
-        let fn_ident = Ident::new(
-          //&format!("on_{}", &msg.to_string().to_snake()),
-          &format!("on_{}", type_to_snake(msg)),
-          Span::call_site(),
-          );
+        #vis fn len(&self) -> usize {
+          self.messages.len()
+        }
 
-          let id = type_last_ident(msg);
+        #vis fn is_empty(&self) -> bool {
+          self.messages.is_empty()
+        }
 
-          quote!{
-            #message_enum_ident::#id(message) => self.#fn_ident(message),
+        /// number of deferred messages
+        #vis fn deferred_len(&self) -> usize {
+          self.deferred.len()
+        }
+
+        fn defer(&mut self, input: #message_enum_ident #type_arg_toks) {
+          self.deferred.push(input);
+        }
+
+        /// the oldest deferred message accepted by the machine, or the next raised one
+        fn next(&mut self, machine: &#machine_name) -> Option<#message_enum_ident #type_arg_toks> {
+          match self.deferred.iter().position(|input| machine.can_handle(input)) {
+            Some(index) => Some(self.deferred.remove(index)),
+            None => self.messages.pop_front(),
           }
+        }
 
-      })
-    .collect::<Vec<_>>();
+        /// removes the deferred messages the machine neither accepts nor defers
+        fn drop_deferred(&mut self, machine: &#machine_name) -> Vec<#message_enum_ident #type_arg_toks> {
+          let (kept, dropped): (Vec<_>, Vec<_>) = self
+            .deferred
+            .drain(..)
+            .partition(|input| machine.can_handle(input) || machine.defers(input));
+          self.deferred = kept;
+          #log_dropped
+          dropped
+        }
 
-    /*let type_arg_toks = if type_arguments.is_empty() {
-      quote!{}
-    } else {
-      quote!{
-        < #(#type_arguments),* >
+        fn clear(&mut self) {
+          self.messages.clear();
+          self.deferred.clear();
+        }
       }
-    };*/
 
-    let execute = quote! {
-      pub fn execute #type_arg_toks(self, input: #message_enum_ident #type_arg_toks) -> #machine_name {
-        match input {
-          #(#matches)*
-          _ => #machine_name::Error,
+      impl #type_arg_toks Default for #queue_ident #type_arg_toks {
+        fn default() -> Self {
+          #queue_ident::new()
         }
       }
-    };
 
-    let toks = quote! {
-      impl #machine_name {
-        #(#functions)*
+      /// the message that moved the machine to the error state
+      #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+      #[allow(dead_code)]
+      #vis struct #run_error_ident {
+        pub from: #kind_ident,
+        pub message: #message_kind_ident,
+      }
 
-        #execute
+      impl std::fmt::Display for #run_error_ident {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+          write!(f, "{}: {} rejected {}", stringify!(#machine_name), self.from, self.message)
+        }
       }
-    };
 
-    stream.extend(proc_macro::TokenStream::from(toks));
+      impl std::error::Error for #run_error_ident {}
 
-    //println!("generated: {:?}", gen);
-    trace!("generated transitions: {}", stream);
-    let _ = create_dir("target/machine");
-    let file_name = format!("target/machine/{}.rs", machine_name.to_string().to_lowercase());
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&file_name)
-        .and_then(|mut file| {
-            file.seek(std::io::SeekFrom::End(0))?;
-            file.write_all(stream.to_string().as_bytes())?;
-            file.flush()
-        })
-        .expect("error writing transitions");
+      #[allow(dead_code)]
+      #vis struct #runner_ident #type_arg_toks {
+        machine: Option<#machine_name>,
+        queue: #queue_ident #type_arg_toks,
+        dropped: Vec<#message_enum_ident #type_arg_toks>,
+        #output_field
+      }
 
-    stream
+      #[allow(dead_code)]
+      impl #type_arg_toks #runner_ident #type_arg_toks {
+        #vis fn new(machine: #machine_name) -> Self {
+          #runner_ident {
+            machine: Some(machine),
+            queue: #queue_ident::new(),
+            dropped: Vec::new(),
+            #output_init
+          }
+        }
+
+        #vis fn machine(&self) -> &#machine_name {
+          self.machine.as_ref().expect("the machine is only taken during a transition")
+        }
+
+        #vis fn into_inner(self) -> #machine_name {
+          self.machine.expect("the machine is only taken during a transition")
+        }
+
+        #take_output
+
+        /// the deferred messages dropped since the last call, because the machine
+        /// reached a state that neither accepts nor defers them
+        #vis fn take_dropped(&mut self) -> Vec<#message_enum_ident #type_arg_toks> {
+          std::mem::replace(&mut self.dropped, Vec::new())
+        }
+
+        /// processes the message, then the messages raised by the handlers until
+        /// the queue is empty, replaying deferred messages first as soon as the
+        /// machine accepts them. Deferred messages are dropped when the machine
+        /// reaches a state that neither accepts nor defers them. Stops at the first
+        /// message moving the machine to the error state, dropping the remaining
+        /// and deferred ones
+        #vis fn send(&mut self, input: #message_enum_ident #type_arg_toks #context_param) -> Result<(), #run_error_ident> {
+          self.queue.push(input);
+
+          while let Some(input) = self.queue.next(self.machine.as_ref().expect("the machine is only taken during a transition")) {
+            let machine = self.machine.take().expect("the machine is only taken during a transition");
+            let from = machine.kind();
+            let message = input.kind();
+
+            let #result = machine.execute(#input #context_forward, &mut self.queue);
+            #output_extend
+            let failed = #failed;
+            self.machine = Some(next);
+
+            if failed {
+              self.queue.clear();
+              return Err(#run_error_ident { from, message });
+            }
+
+            let dropped = self
+              .queue
+              .drop_deferred(self.machine.as_ref().expect("the machine is only taken during a transition"));
+            self.dropped.extend(dropped);
+          }
+
+          Ok(())
+        }
+      }
+    }
+}
+
+// with the `runtime-log` feature, wraps the next state expression in a debug record
+fn log_transition(machine_name: &Ident, start: &Ident, message: &str, next: TokenStream2) -> TokenStream2 {
+    if cfg!(feature = "runtime-log") {
+        quote! {
+          {
+            let next = #next;
+            extern crate log;
+            log::debug!("{}: {} -> {} on {}", stringify!(#machine_name), stringify!(#start), next.kind(), #message);
+            next
+          }
+        }
+    } else {
+        next
+    }
+}
+
+// with the `runtime-log` feature, emits a debug record when a message is deferred
+fn log_deferred(machine_name: &Ident, start: &Ident, message: &str) -> TokenStream2 {
+    if cfg!(feature = "runtime-log") {
+        quote! {
+          extern crate log;
+          log::debug!("{}: {} deferred {}", stringify!(#machine_name), stringify!(#start), #message);
+        }
+    } else {
+        quote! {}
+    }
+}
+
+// with the `runtime-log` feature, emits a warning when a message moves the machine to the error state
+fn log_rejected(machine_name: &Ident, error: &Ident, message: &str) -> TokenStream2 {
+    let next = quote! { #machine_name::#error };
+    if cfg!(feature = "runtime-log") {
+        let error = error.to_string();
+        quote! {
+          {
+            extern crate log;
+            log::warn!("{}: {} rejected {}, moving to {}", stringify!(#machine_name), self.kind(), #message, #error);
+            #next
+          }
+        }
+    } else {
+        next
+    }
 }
 
 #[proc_macro]
@@ -840,35 +4495,57 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
     //println!("\ninput: {:?}", input);
     let mut stream = proc_macro::TokenStream::new();
 
+    let tokens = TokenStream2::from(input.clone());
     let methods = parse_macro_input!(input as Methods);
     trace!("\nparsed methods: {:#?}", methods);
 
+    // with the `inherit` option, the machine's settings are looked up first
+    if methods.defaults.is_none() && methods.inherit {
+        return proc_macro::TokenStream::from(impl_defaults_lookup(&methods.machine_name, "methods", tokens));
+    }
+    stream.extend(proc_macro::TokenStream::from(impl_defaults_check(
+        &methods.machine_name,
+        &[(namespace_setting(methods.namespace), methods.machine_name.span())],
+    )));
+
     let mut h = HashMap::new();
     for method in methods.methods.iter() {
         for state in method.states.iter() {
             let entry = h.entry(state).or_insert(Vec::new());
-            entry.push(&method.method_type);
+            entry.push(method);
         }
     }
 
-    for (state, methods) in h.iter() {
-        let method_toks = methods
+    // an entry's visibility overrides the one written before the machine name,
+    // which overrides the machine's one
+    let default_vis = match (&methods.vis, methods.defaults.as_ref()) {
+        (&Visibility::Inherited, Some(defaults)) => defaults.vis.clone(),
+        (vis, _) => item_vis(vis),
+    };
+    let method_vis = |method: &Method| match method.vis {
+        Visibility::Inherited => default_vis.clone(),
+        ref vis => quote! { #vis },
+    };
+
+    for (state, state_methods) in h.iter() {
+        let method_toks = state_methods
             .iter()
             .map(|method| {
-                match method {
-                    MethodType::Get(ident, ty) => {
+                let vis = method_vis(method);
+                match &method.method_type {
+                    MethodType::Get(ident, member, ty) => {
                         quote! {
-                          pub fn #ident(&self) -> &#ty {
-                            &self.#ident
+                          #vis fn #ident(&self) -> &#ty {
+                            &self.#member
                           }
                         }
                     }
-                    MethodType::Set(ident, ty) => {
+                    MethodType::Set(ident, member, ty) => {
                         let mut_ident =
                             Ident::new(&format!("{}_mut", &ident.to_string()), Span::call_site());
                         quote! {
-                          pub fn #mut_ident(&mut self) -> &mut #ty {
-                            &mut self.#ident
+                          #vis fn #mut_ident(&mut self) -> &mut #ty {
+                            &mut self.#member
                           }
                         }
                     }
@@ -880,6 +4557,7 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
             })
             .collect::<Vec<_>>();
 
+        let state = state_path(&namespace_ident(&methods.machine_name, methods.namespace), state);
         let toks = quote! {
           impl #state {
             #(#method_toks)*
@@ -893,8 +4571,9 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
     let wrapper_methods = methods
         .methods
         .iter()
-        .map(|method| match &method.method_type {
-            MethodType::Get(ident, ty) => {
+        .map(|method| (method, method_vis(method)))
+        .map(|(method, vis)| match &method.method_type {
+            MethodType::Get(ident, _, ty) => {
                 let variants = method
                     .states
                     .iter()
@@ -905,7 +4584,7 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                     })
                     .collect::<Vec<_>>();
                 quote! {
-                  pub fn #ident(&self) -> Option<&#ty> {
+                  #vis fn #ident(&self) -> Option<&#ty> {
                     match self {
                       #(#variants)*
                       _ => None,
@@ -913,7 +4592,7 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                   }
                 }
             }
-            MethodType::Set(ident, ty) => {
+            MethodType::Set(ident, _, ty) => {
                 let mut_ident =
                     Ident::new(&format!("{}_mut", &ident.to_string()), Span::call_site());
 
@@ -927,7 +4606,7 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                     })
                     .collect::<Vec<_>>();
                 quote! {
-                  pub fn #mut_ident(&mut self) -> Option<&mut #ty> {
+                  #vis fn #mut_ident(&mut self) -> Option<&mut #ty> {
                     match self {
                       #(#variants)*
                       _ => None,
@@ -972,6 +4651,17 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                     .collect::<Vec<_>>();
 
                 let inputs = &m.decl.inputs;
+                // the body uses the receiver written in the signature, which may
+                // come from another hygiene context than the generated tokens
+                let receiver = inputs
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        FnArg::SelfRef(arg) => Some(arg.self_token),
+                        FnArg::SelfValue(arg) => Some(arg.self_token),
+                        _ => None,
+                    })
+                    .next()
+                    .unwrap_or_default();
                 let output = match &m.decl.output {
                     ReturnType::Default => quote! {},
                     ReturnType::Type(arrow, ty) => {
@@ -990,8 +4680,8 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                 match method.default {
                     DefaultValue::None => {
                         quote! {
-                          pub fn #ident(#inputs) #output {
-                            match self {
+                          #vis fn #ident(#inputs) #output {
+                            match #receiver {
                               #(#variants)*
                               _ => None,
                             }
@@ -1000,8 +4690,8 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                     }
                     DefaultValue::Default => {
                         quote! {
-                          pub fn #ident(#inputs) #output {
-                            match self {
+                          #vis fn #ident(#inputs) #output {
+                            match #receiver {
                               #(#variants)*
                               _ => std::default::Default::default(),
                             }
@@ -1010,8 +4700,8 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
                     }
                     DefaultValue::Val(ref expr) => {
                         quote! {
-                          pub fn #ident(#inputs) #output {
-                            match self {
+                          #vis fn #ident(#inputs) #output {
+                            match #receiver {
                               #(#variants)*
                               _ => #expr,
                             }
@@ -1049,21 +4739,30 @@ pub fn methods(input: proc_macro::TokenStream) -> syn::export::TokenStream {
 
 #[derive(Debug)]
 struct Methods {
+    /// the defaults given by `machine!`, when they were looked up
+    pub defaults: Option<MachineDefaults>,
+    pub vis: Visibility,
     pub machine_name: Ident,
+    /// the machine defines its state structs in a module
+    pub namespace: bool,
+    /// take the machine's visibility from the macro `machine!` defines
+    pub inherit: bool,
     pub methods: Vec<Method>,
 }
 
 #[derive(Debug)]
 struct Method {
+    pub vis: Visibility,
     pub states: Vec<Ident>,
     pub method_type: MethodType,
     pub default: DefaultValue,
 }
 
+/// accessors are named after the field, or after the name given to a positional field
 #[derive(Debug)]
 enum MethodType {
-    Get(Ident, Type),
-    Set(Ident, Type),
+    Get(Ident, Member, Type),
+    Set(Ident, Member, Type),
     Fn(MethodSig),
 }
 
@@ -1085,9 +4784,27 @@ impl DefaultValue {
 
 impl Parse for Methods {
     fn parse(input: ParseStream) -> Result<Self> {
+        let defaults = MachineDefaults::parse_optional(input)?;
+        let vis: Visibility = input.parse()?;
         let machine_name: Ident = input.parse()?;
         let _: Token![,] = input.parse()?;
 
+        let mut namespace = false;
+        let mut inherit = false;
+        while !input.peek(syn::token::Bracket) {
+            let name: Ident = input.parse()?;
+            match name.to_string().as_str() {
+                "namespace" => namespace = true,
+                "inherit" => inherit = true,
+                _ => return Err(syn::Error::new(name.span(), format!("unknown option `{}`", name))),
+            }
+            let _: Token![,] = input.parse()?;
+        }
+        if let Some(ref defaults) = defaults {
+            check_namespace(&machine_name, namespace, defaults)?;
+            namespace = defaults.namespace;
+        }
+
         let content;
         bracketed!(content in input);
 
@@ -1108,7 +4825,11 @@ impl Parse for Methods {
         }
 
         Ok(Methods {
+            defaults,
+            vis,
             machine_name,
+            namespace,
+            inherit,
             methods,
         })
     }
@@ -1145,6 +4866,7 @@ impl Parse for Method {
         }
 
         let _: Token![=>] = input.parse()?;
+        let vis: Visibility = input.parse()?;
         let default_token: Option<Token![default]> = input.parse()?;
         let default = if default_token.is_some() {
             match input.parse::<ParenVal>() {
@@ -1159,14 +4881,28 @@ impl Parse for Method {
             Ok(f) => MethodType::Fn(f),
             Err(_) => {
                 let i: Ident = input.parse()?;
-                let name: Ident = input.parse()?;
+                let (name, member) = if input.peek(syn::LitInt) {
+                    let index: Index = input.parse()?;
+                    if !input.peek(Token![as]) {
+                        return Err(syn::Error::new(
+                            index.span,
+                            "positional fields need a name, add `as name` after the index",
+                        ));
+                    }
+                    let _: Token![as] = input.parse()?;
+                    let name: Ident = input.parse()?;
+                    (name, Member::Unnamed(index))
+                } else {
+                    let name: Ident = input.parse()?;
+                    (name.clone(), Member::Named(name))
+                };
                 let _: Token![:] = input.parse()?;
                 let ty: Type = input.parse()?;
 
                 if i.to_string() == "get" {
-                    MethodType::Get(name, ty)
+                    MethodType::Get(name, member, ty)
                 } else if i.to_string() == "set" {
-                    MethodType::Set(name, ty)
+                    MethodType::Set(name, member, ty)
                 } else {
                     return Err(syn::Error::new(i.span(), "expected `get` or `set`"));
                 }
@@ -1174,6 +4910,7 @@ impl Parse for Method {
         };
 
         Ok(Method {
+            vis,
             states,
             method_type,
             default,
@@ -1218,51 +4955,116 @@ fn parse_method_sig(input: ParseStream) -> Result<MethodSig> {
     })
 }
 
-fn type_to_snake(t: &Type) -> String {
+// default name of a message: the last segment of its path
+fn type_last_ident(t: &Type) -> Option<&Ident> {
   match t {
-    Type::Path(ref p) => {
-      match p.path.segments.last() {
-        Some(Pair::End(segment)) => {
-          segment.ident.to_string().to_snake()
-        },
-        _ => panic!("expected a path segment"),
-      }
-    },
-    t => panic!("expected a Type::Path, got {:?}", t),
+    Type::Path(ref p) if p.qself.is_none() => p.path.segments.last().map(|segment| &segment.value().ident),
+    _ => None,
   }
 }
 
-fn type_last_ident(t: &Type) -> &Ident {
-  match t {
-    Type::Path(ref p) => {
-      match p.path.segments.last() {
-        Some(Pair::End(segment)) => {
-          &segment.ident
-        },
-        _ => panic!("expected a path segment"),
+fn impl_outcome(args: &HandlerArgs, machine_name: &Ident, outcome_ident: &Ident, ends: &[Ident]) -> TokenStream2 {
+  let vis = &args.vis;
+  let ends2 = &ends.iter().map(|end| args.state(end)).collect::<Vec<_>>();
+  let from_arms = ends
+    .iter()
+    .map(|end| {
+      let value = args.state_value(end, quote! { state });
+      quote! { #outcome_ident::#end(state) => #value, }
+    })
+    .collect::<Vec<_>>();
+
+  quote! {
+    #vis enum #outcome_ident {
+      #(#ends(#ends2)),*
+    }
+
+    impl From<#outcome_ident> for #machine_name {
+      fn from(outcome: #outcome_ident) -> #machine_name {
+        match outcome {
+          #(#from_arms)*
+        }
       }
-    },
-    t => panic!("expected a Type::Path, got {:?}", t),
+    }
   }
 }
 
+// generic arguments of a message: the lifetimes it borrows, and the type
+// parameters passed to its last path segment
 fn type_args(t: &Type) -> HashSet<GenericArgument> {
+  let mut args = HashSet::new();
+  if let Type::Path(ref p) = t {
+    if let Some(segment) = p.path.segments.last() {
+      if let PathArguments::AngleBracketed(ref a) = segment.value().arguments {
+        for arg in a.args.iter() {
+          if let GenericArgument::Type(Type::Path(ref p)) = arg {
+            let bare = p.path.leading_colon.is_none()
+              && p.path.segments.len() == 1
+              && p.path.segments[0].arguments.is_empty();
+            if p.qself.is_none() && bare && !is_primitive(&p.path.segments[0].ident) {
+              args.insert(arg.clone());
+            }
+          }
+        }
+      }
+    }
+  }
+  type_lifetimes(t, &mut args);
+  args
+}
+
+fn type_lifetimes(t: &Type, args: &mut HashSet<GenericArgument>) {
   match t {
     Type::Path(ref p) => {
-      match p.path.segments.last() {
-        Some(Pair::End(segment)) => {
-          match &segment.arguments {
-            PathArguments::AngleBracketed(a) => {
-              a.args.iter().cloned().collect()
-            },
-            PathArguments::None => HashSet::new(),
-            a => panic!("expected angle bracketed arguments, got {:?}", a),
+      if let Some(ref qself) = p.qself {
+        type_lifetimes(&qself.ty, args);
+      }
+      for segment in p.path.segments.iter() {
+        if let PathArguments::AngleBracketed(ref a) = segment.arguments {
+          for arg in a.args.iter() {
+            match arg {
+              GenericArgument::Lifetime(l) => insert_lifetime(l, args),
+              GenericArgument::Type(t) => type_lifetimes(t, args),
+              _ => {}
+            }
           }
-        },
-        _ => panic!("expected a path segment"),
+        }
+      }
+    }
+    Type::Reference(ref r) => {
+      if let Some(ref l) = r.lifetime {
+        insert_lifetime(l, args);
+      }
+      type_lifetimes(&r.elem, args);
+    }
+    Type::Tuple(ref t) => t.elems.iter().for_each(|t| type_lifetimes(t, args)),
+    Type::Slice(ref s) => type_lifetimes(&s.elem, args),
+    Type::Array(ref a) => type_lifetimes(&a.elem, args),
+    Type::Ptr(ref p) => type_lifetimes(&p.elem, args),
+    Type::Paren(ref p) => type_lifetimes(&p.elem, args),
+    Type::Group(ref g) => type_lifetimes(&g.elem, args),
+    Type::TraitObject(ref o) => {
+      for bound in o.bounds.iter() {
+        if let TypeParamBound::Lifetime(ref l) = bound {
+          insert_lifetime(l, args);
+        }
       }
-    },
-    t => panic!("expected a Type::Path, got {:?}", t),
+    }
+    _ => {}
+  }
+}
+
+fn is_primitive(ident: &Ident) -> bool {
+  const PRIMITIVES: &[&str] = &[
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize",
+    "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+  ];
+  PRIMITIVES.iter().any(|p| ident == p)
+}
+
+fn insert_lifetime(l: &Lifetime, args: &mut HashSet<GenericArgument>) {
+  if l.ident != "static" && l.ident != "_" {
+    args.insert(GenericArgument::Lifetime(l.clone()));
   }
 }
 
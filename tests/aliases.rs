@@ -0,0 +1,120 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Player {
+    Stopped,
+    Playing { track: u8 },
+    Seeking { position: (u8, u8) },
+  }
+);
+
+pub mod local {
+  #[derive(Clone,Debug,PartialEq)]
+  pub struct Start;
+}
+
+pub mod remote {
+  #[derive(Clone,Debug,PartialEq)]
+  pub struct Start { pub track: u8 }
+}
+
+transitions!(Player,
+  [
+    (Stopped, local::Start as StartLocal) => Playing,
+    (Stopped, remote::Start as StartRemote) => Playing,
+    (Playing, (u8, u8) as Seek) => Seeking,
+    (Seeking, &'a [u8] as Bytes) => Playing,
+    (Playing, Vec<u8> as Playlist) => Playing
+  ]
+);
+
+impl Stopped {
+  pub fn on_start_local(self, _: local::Start) -> Playing {
+    Playing { track: 0 }
+  }
+
+  pub fn on_start_remote(self, input: remote::Start) -> Playing {
+    Playing { track: input.track }
+  }
+}
+
+impl Playing {
+  pub fn on_seek(self, input: (u8, u8)) -> Seeking {
+    Seeking { position: input }
+  }
+
+  pub fn on_playlist(self, input: Vec<u8>) -> Playing {
+    Playing { track: input[0] }
+  }
+}
+
+impl Seeking {
+  pub fn on_bytes<'a>(self, input: &'a [u8]) -> Playing {
+    Playing { track: self.position.0 + input.len() as u8 }
+  }
+}
+
+#[test]
+fn aliases() {
+  let player = Player::stopped();
+  assert_eq!(player.clone().on_start_local(local::Start), Player::playing(0));
+  assert_eq!(player.execute(PlayerMessages::StartRemote(remote::Start { track: 3 })), Player::playing(3));
+
+  let player = Player::playing(3).execute(PlayerMessages::Seek((4, 20)));
+  assert_eq!(player, Player::seeking((4, 20)));
+
+  let bytes = [0u8; 2];
+  let player = player.execute(PlayerMessages::Bytes(&bytes[..]));
+  assert_eq!(player, Player::playing(6));
+
+  assert_eq!(PlayerMessages::Seek((0, 0)).kind(), PlayerMessageKind::Seek);
+  assert_eq!(Player::stopped().accepted_messages(), &[PlayerMessageKind::StartLocal, PlayerMessageKind::StartRemote]);
+}
+
+#[test]
+fn generic_message() {
+  let player = Player::playing(1).execute(PlayerMessages::Playlist(vec![7, 8]));
+  assert_eq!(player, Player::playing(7));
+}
+
+pub trait Event: std::fmt::Debug {
+  fn name(&self) -> &str;
+}
+
+#[derive(Debug)]
+pub struct Knock;
+
+impl Event for Knock {
+  fn name(&self) -> &str {
+    "knock"
+  }
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Door {
+    Closed,
+    Open { by: String },
+  }
+);
+
+// a trait object is neither `Clone` nor `PartialEq`
+transitions!(Door, derive(Debug),
+  [
+    (Closed, Box<dyn Event> as Boxed) => Open
+  ]
+);
+
+impl Closed {
+  pub fn on_boxed(self, input: Box<dyn Event>) -> Open {
+    Open { by: input.name().to_string() }
+  }
+}
+
+#[test]
+fn trait_object() {
+  let door = Door::closed().execute(DoorMessages::Boxed(Box::new(Knock)));
+  assert_eq!(door, Door::open("knock".to_string()));
+}
@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Upload {
+    /// waiting for the first chunk
+    #[derive(Default)]
+    Pending,
+    Sending {
+      /// bytes sent so far
+      sent: usize,
+      #[cfg(any())]
+      checksum: u32,
+    },
+    #[cfg(any())]
+    Paused { sent: usize },
+    Done,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Chunk(usize);
+
+transitions!(Upload,
+  [
+    (Pending, Chunk) => Sending,
+    (Sending, Chunk) => [Sending, Done]
+  ]
+);
+
+impl Pending {
+  pub fn on_chunk(self, input: Chunk) -> Sending {
+    Sending { sent: input.0 }
+  }
+}
+
+impl Sending {
+  pub fn on_chunk(self, input: Chunk) -> SendingChunkOutcome {
+    if input.0 == 0 {
+      SendingChunkOutcome::Done(Done {})
+    } else {
+      SendingChunkOutcome::Sending(Sending { sent: self.sent + input.0 })
+    }
+  }
+}
+
+#[test]
+fn variant_attributes() {
+  assert_eq!(Pending::default(), Pending {});
+  assert_eq!(Upload::sending(3), Upload::Sending(Sending { sent: 3 }));
+
+  let upload = Upload::pending().execute(UploadMessages::Chunk(Chunk(3)));
+  assert_eq!(upload.execute(UploadMessages::Chunk(Chunk(0))), Upload::done());
+}
+
+#[test]
+fn disabled_variant() {
+  assert!("Paused".parse::<UploadKind>().is_err());
+  assert_eq!("Done".parse::<UploadKind>(), Ok(UploadKind::Done));
+  assert_eq!(Upload::done().kind(), UploadKind::Done);
+}
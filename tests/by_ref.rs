@@ -0,0 +1,96 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Subscriber {
+    Waiting,
+    Received { total: usize },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Publish<'a> {
+  payload: &'a [u8],
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Reset;
+
+transitions!(Subscriber, by_ref,
+  [
+    (Waiting, Publish<'a>) => Received,
+    (Received, Publish<'a>) => Received,
+    (Received, Reset) => Waiting
+  ]
+);
+
+impl Waiting {
+  pub fn on_publish(self, input: &Publish) -> Received {
+    Received { total: input.payload.len() }
+  }
+}
+
+impl Received {
+  pub fn on_publish(self, input: &Publish) -> Received {
+    Received { total: self.total + input.payload.len() }
+  }
+
+  pub fn on_reset(self, _: &Reset) -> Waiting {
+    Waiting {}
+  }
+}
+
+#[test]
+fn broadcast() {
+  let payload = vec![1, 2, 3];
+  let message = SubscriberMessages::Publish(Publish { payload: &payload });
+
+  let subscribers = vec![Subscriber::waiting(), Subscriber::received(2), Subscriber::error()];
+  let subscribers = subscribers
+    .into_iter()
+    .map(|s| s.execute(&message))
+    .collect::<Vec<_>>();
+
+  assert_eq!(subscribers, vec![Subscriber::received(3), Subscriber::received(5), Subscriber::error()]);
+  assert_eq!(message, SubscriberMessages::Publish(Publish { payload: &[1, 2, 3] }));
+
+  let reset = Reset;
+  assert_eq!(subscribers[1].clone().on_reset(&reset), Subscriber::waiting());
+  assert_eq!(subscribers[1].clone().on_publish(&Publish { payload: &payload[..1] }), Subscriber::received(6));
+}
+
+#[derive(Default)]
+struct Payloads {
+  transitions: Vec<usize>,
+  rejected: Vec<usize>,
+}
+
+impl<'a> SubscriberObserver<'a> for Payloads {
+  fn on_transition(&mut self, _: SubscriberKind, message: &SubscriberMessages<'a>, _: SubscriberKind) {
+    if let SubscriberMessages::Publish(ref publish) = *message {
+      self.transitions.push(publish.payload.len());
+    }
+  }
+
+  fn on_rejected(&mut self, _: SubscriberKind, message: &SubscriberMessages<'a>) {
+    if let SubscriberMessages::Publish(ref publish) = *message {
+      self.rejected.push(publish.payload.len());
+    }
+  }
+}
+
+#[test]
+fn observer() {
+  let payload = vec![1, 2];
+  let message = SubscriberMessages::Publish(Publish { payload: &payload });
+  let mut payloads = Payloads::default();
+
+  let subscriber = Subscriber::waiting().execute_with(&mut payloads, &message);
+  assert_eq!(subscriber, Subscriber::received(2));
+  assert_eq!(payloads.transitions, vec![2]);
+  assert!(payloads.rejected.is_empty());
+
+  assert_eq!(Subscriber::error().execute_with(&mut payloads, &message), Subscriber::error());
+  assert_eq!(payloads.rejected, vec![2]);
+}
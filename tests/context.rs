@@ -0,0 +1,76 @@
+#[macro_use]
+extern crate machine;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Exchange {
+  request: Option<String>,
+  headers: Vec<String>,
+}
+
+machine!(
+  #[derive(Clone, Debug, PartialEq)]
+  #[machine(context = Exchange)]
+  enum Http {
+    Initial,
+    Headers,
+    Done { length: usize },
+  }
+);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestLine(String);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header(String);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderEnd;
+
+transitions!(Http, context,
+  [
+    (Initial, RequestLine) => Headers,
+    (Headers, Header) => Headers,
+    (Headers, HeaderEnd) => Done
+  ]
+);
+
+impl Initial {
+  pub fn on_request_line(self, input: RequestLine, context: &mut Exchange) -> Headers {
+    context.request = Some(input.0);
+    Headers {}
+  }
+}
+
+impl Headers {
+  pub fn on_header(self, input: Header, context: &mut HttpContext) -> Headers {
+    context.headers.push(input.0);
+    Headers {}
+  }
+
+  pub fn on_header_end(self, _: HeaderEnd, context: &mut HttpContext) -> Done {
+    Done { length: context.headers.len() }
+  }
+}
+
+#[test]
+fn context() {
+  let mut http = HttpMachine::new(Http::initial(), Exchange::default());
+
+  http.execute(HttpMessages::RequestLine(RequestLine("GET / HTTP/1.1".to_string())));
+  http.execute(HttpMessages::Header(Header("Host: lolcatho.st".to_string())));
+  assert_eq!(http.state(), &Http::headers());
+  assert_eq!(http.context().request, Some("GET / HTTP/1.1".to_string()));
+
+  http.context_mut().headers.push("Accept: */*".to_string());
+  http.execute(HttpMessages::HeaderEnd(HeaderEnd));
+  http.execute(HttpMessages::HeaderEnd(HeaderEnd));
+
+  let (state, context) = http.into_parts();
+  assert_eq!(state, Http::error());
+  assert_eq!(context.headers.len(), 2);
+
+  let mut context = Exchange::default();
+  let state = Http::initial().on_request_line(RequestLine("GET /".to_string()), &mut context);
+  assert_eq!(state, Http::headers());
+  assert_eq!(context.request, Some("GET /".to_string()));
+}
@@ -0,0 +1,102 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Stream {
+    Handshaking,
+    Open { frames: Vec<u8> },
+    Closed { frames: Vec<u8> },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Handshake;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Data {
+  frame: u8,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Close;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Abort;
+
+transitions!(Stream, runner,
+  [
+    (Handshaking, Handshake) => Open,
+    (Handshaking, Data) => defer,
+    (Handshaking, Close) => defer,
+    (Handshaking, Abort) => Closed,
+    (Open, Data) => Open,
+    (Open, Close) => Closed
+  ]
+);
+
+impl Handshaking {
+  pub fn on_handshake(self, _: Handshake, _: &mut StreamQueue) -> Open {
+    Open { frames: Vec::new() }
+  }
+
+  pub fn on_abort(self, _: Abort, _: &mut StreamQueue) -> Closed {
+    Closed { frames: Vec::new() }
+  }
+}
+
+impl Open {
+  pub fn on_data(mut self, input: Data, _: &mut StreamQueue) -> Open {
+    self.frames.push(input.frame);
+    self
+  }
+
+  pub fn on_close(self, _: Close, _: &mut StreamQueue) -> Closed {
+    Closed { frames: self.frames }
+  }
+}
+
+#[test]
+fn defer() {
+  let handshaking = Stream::handshaking();
+  assert!(handshaking.defers(&StreamMessages::Data(Data { frame: 1 })));
+  assert!(!handshaking.can_handle(&StreamMessages::Data(Data { frame: 1 })));
+  assert_eq!(handshaking.next_state_kind(&StreamMessages::Close(Close)), Some(StreamKind::Handshaking));
+
+  let mut runner = StreamRunner::new(handshaking);
+  assert_eq!(runner.send(StreamMessages::Data(Data { frame: 1 })), Ok(()));
+  assert_eq!(runner.send(StreamMessages::Data(Data { frame: 2 })), Ok(()));
+  assert_eq!(runner.send(StreamMessages::Close(Close)), Ok(()));
+  assert_eq!(runner.machine(), &Stream::handshaking());
+
+  // deferred messages are replayed in arrival order once accepted
+  assert_eq!(runner.send(StreamMessages::Handshake(Handshake)), Ok(()));
+  assert_eq!(runner.machine(), &Stream::closed(vec![1, 2]));
+
+  assert_eq!(runner.send(StreamMessages::Close(Close)), Err(StreamRunError {
+    from: StreamKind::Closed,
+    message: StreamMessageKind::Close,
+  }));
+}
+
+#[test]
+fn execute() {
+  let mut queue = StreamQueue::new();
+  let stream = Stream::handshaking().execute(StreamMessages::Data(Data { frame: 1 }), &mut queue);
+  assert_eq!(stream, Stream::handshaking());
+  assert_eq!(queue.deferred_len(), 1);
+  assert!(queue.is_empty());
+}
+
+#[test]
+fn dropped() {
+  let mut runner = StreamRunner::new(Stream::handshaking());
+  assert_eq!(runner.send(StreamMessages::Data(Data { frame: 1 })), Ok(()));
+  assert_eq!(runner.send(StreamMessages::Close(Close)), Ok(()));
+
+  // the closed state neither accepts nor defers the waiting messages
+  assert_eq!(runner.send(StreamMessages::Abort(Abort)), Ok(()));
+  assert_eq!(runner.machine(), &Stream::closed(vec![]));
+  assert_eq!(runner.take_dropped(), vec![StreamMessages::Data(Data { frame: 1 }), StreamMessages::Close(Close)]);
+  assert!(runner.take_dropped().is_empty());
+}
@@ -0,0 +1,82 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[machine(error = Invalid)]
+  enum Compile {
+    Parsing,
+    Error { line: u32 },
+    Done,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Fail { line: u32 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Finish;
+
+transitions!(pub(crate) Compile, inherit,
+  [
+    (Parsing, Fail) => Error,
+    (Parsing, Finish) => Done
+  ]
+);
+
+impl Parsing {
+  pub fn on_fail(self, input: Fail) -> Error {
+    Error { line: input.line }
+  }
+
+  pub fn on_finish(self, _: Finish) -> Done {
+    Done {}
+  }
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[machine(no_error)]
+  enum Switch {
+    On,
+    Off,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Toggle;
+
+transitions!(Switch, no_error, runner,
+  [
+    (On, Toggle) => Off,
+    (Off, Toggle) => On
+  ]
+);
+
+impl On {
+  pub fn on_toggle(self, _: Toggle, _: &mut SwitchQueue) -> Off {
+    Off {}
+  }
+}
+
+impl Off {
+  pub fn on_toggle(self, _: Toggle, _: &mut SwitchQueue) -> On {
+    On {}
+  }
+}
+
+#[test]
+fn renamed_error() {
+  assert_eq!(Compile::parsing().execute(CompileMessages::Fail(Fail { line: 3 })), Compile::error(3));
+  assert_eq!(Compile::error(3).execute(CompileMessages::Finish(Finish)), Compile::invalid());
+  assert_eq!(Compile::invalid().kind(), CompileKind::Invalid);
+  assert_eq!(Compile::done().next_state_kind(&CompileMessages::Finish(Finish)), Some(CompileKind::Invalid));
+}
+
+#[test]
+fn without_error() {
+  let mut runner = SwitchRunner::new(Switch::on());
+  assert_eq!(runner.send(SwitchMessages::Toggle(Toggle)), Ok(()));
+  assert_eq!(runner.machine(), &Switch::off());
+  assert_eq!("Error".parse::<SwitchKind>().is_err(), true);
+}
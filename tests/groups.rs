@@ -0,0 +1,95 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Lamp {
+    Off,
+    On { level: u8 },
+    Dimmed { level: u8 },
+  }
+);
+
+transitions!(Lamp, group = basic,
+  [
+    (Off, Switch) => On,
+    (On, Switch) => Off
+  ]
+);
+
+#[macro_use]
+mod dimming {
+  use super::*;
+
+  transitions!(Lamp, group = dim,
+    [
+      (On, Dim) => Dimmed,
+      (Dimmed, Dim) => Dimmed,
+      (Dimmed, Switch) => Off,
+    ]
+  );
+
+  impl On {
+    pub fn on_dim(self, input: Dim) -> Dimmed {
+      Dimmed { level: self.level - input.0 }
+    }
+  }
+
+  impl Dimmed {
+    pub fn on_dim(self, input: Dim) -> Dimmed {
+      Dimmed { level: self.level.saturating_sub(input.0) }
+    }
+
+    pub fn on_switch(self, _: Switch) -> Off {
+      Off {}
+    }
+  }
+}
+
+// never enabled, the merged machine leaves these rows out
+#[cfg(any())]
+#[macro_use]
+mod disabled {
+  transitions!(Lamp, group = disabled,
+    [
+      (Off, Dim) => Dimmed
+    ]
+  );
+}
+
+transitions_finish!(Lamp,
+  messages {
+    Switch,
+    Dim(u8),
+  },
+  [basic, dim, #[cfg(any())] disabled]
+);
+
+impl Off {
+  pub fn on_switch(self, _: Switch) -> On {
+    On { level: 10 }
+  }
+}
+
+impl On {
+  pub fn on_switch(self, _: Switch) -> Off {
+    Off {}
+  }
+}
+
+#[test]
+fn merged() {
+  let lamp = Lamp::off().execute(Switch.into());
+  assert_eq!(lamp, Lamp::on(10));
+
+  let lamp = lamp.execute(Dim(4).into()).execute(LampMessages::Dim(Dim(4)));
+  assert_eq!(lamp, Lamp::dimmed(2));
+  assert_eq!(lamp.accepted_messages(), &[LampMessageKind::Dim, LampMessageKind::Switch]);
+  assert_eq!(lamp.execute(Switch.into()), Lamp::off());
+}
+
+#[test]
+fn disabled_group() {
+  assert!(!Lamp::off().can_handle(&LampMessages::Dim(Dim(1))));
+  assert_eq!(Lamp::off().execute(Dim(1).into()), Lamp::error());
+}
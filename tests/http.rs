@@ -154,3 +154,15 @@ impl HasHostAndLength {
     }
   }
 }
+
+#[test]
+fn header_end() {
+  let mut r = HttpRequest::Initial(Initial {});
+  r = r.on_request_line(RequestLine);
+  r = r.on_host_header(HostHeader("example.com".to_string()));
+  r = r.on_length_header(LengthHeader(LengthInfo::Chunked));
+  r = r.on_header_end(HeaderEnd);
+
+  assert_eq!(r.kind(), HttpRequestKind::RequestWithChunks);
+  assert_eq!(r.host(), Some("example.com"));
+}
@@ -0,0 +1,107 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::Duration;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+    BlinkingOrange,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar { count: u8 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Toggle;
+
+transitions!(Traffic,
+  [
+    (Green, Advance) => Orange || Orange {},
+    (Orange, Advance) => Red |_, _| Red {},
+    (Red, Advance) => Green,
+    (Green, PassCar) => [Green, Orange] |s, m: PassCar| {
+      let count = s.count + m.count;
+      if count >= 10 {
+        GreenPassCarOutcome::Orange(Orange {})
+      } else {
+        GreenPassCarOutcome::Green(Green { count })
+      }
+    },
+    (Red, after(30s)) => Green |_| Green { count: 0 },
+    (BlinkingOrange, Toggle) => Red
+  ]
+);
+
+impl Red {
+  pub fn on_advance(self, _: Advance) -> Green {
+    Green { count: 0 }
+  }
+}
+
+impl BlinkingOrange {
+  pub fn on_toggle(self, _: Toggle) -> Red {
+    Red {}
+  }
+}
+
+#[test]
+fn inline() {
+  let t = Traffic::green(0);
+  let t = t.on_pass_car(PassCar { count: 5 });
+  assert_eq!(t, Traffic::green(5));
+  let t = t.on_pass_car(PassCar { count: 7 });
+  assert_eq!(t, Traffic::orange());
+  let t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::red());
+  assert_eq!(t.timeout(), Some(Duration::from_secs(30)));
+  let t = t.on_timeout();
+  assert_eq!(t, Traffic::green(0));
+  let t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::orange());
+
+  // the generated handlers can be called directly
+  assert_eq!(Orange {}.on_advance(Advance), Red {});
+}
+
+#[derive(Clone,Debug,PartialEq,Default)]
+pub struct Tally {
+  pub total: u8,
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[machine(context = Tally)]
+  enum Gate {
+    Closed,
+    Open,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pay(u8);
+
+// the message is bound to `context`, the context to `tally`
+transitions!(Gate, context,
+  [
+    (Closed, Pay) => Open |_, context, tally| {
+      tally.total += context.0;
+      Open {}
+    }
+  ]
+);
+
+#[test]
+fn parameter_names() {
+  let mut gate = GateMachine::new(Gate::closed(), Tally::default());
+  gate.execute(GateMessages::Pay(Pay(3)));
+  assert_eq!(gate.state(), &Gate::open());
+  assert_eq!(gate.context().total, 3);
+}
@@ -0,0 +1,107 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Download {
+    #[initial]
+    Queued { retries: u8, url: String },
+    Running(u64),
+    #[final]
+    Complete,
+    #[final]
+    Cancelled { reason: String },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Start;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Finish;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Cancel;
+
+transitions!(Download,
+  [
+    (Queued, Start) => Running,
+    (Running, Finish) => Complete,
+    (Running, Cancel) => Cancelled,
+    (Cancelled, Cancel) => Cancelled
+  ]
+);
+
+impl Queued {
+  pub fn on_start(self, _: Start) -> Running {
+    Running(0)
+  }
+}
+
+impl Running {
+  pub fn on_finish(self, _: Finish) -> Complete {
+    Complete
+  }
+
+  pub fn on_cancel(self, _: Cancel) -> Cancelled {
+    Cancelled { reason: format!("cancelled after {} bytes", self.0) }
+  }
+}
+
+impl Cancelled {
+  pub fn on_cancel(self, _: Cancel) -> Cancelled {
+    self
+  }
+}
+
+#[test]
+fn initial() {
+  assert_eq!(Download::initial(), Download::queued(0, String::new()));
+  assert_eq!(Download::default(), Download::initial());
+}
+
+#[test]
+fn final_states() {
+  let download = Download::initial().execute(DownloadMessages::Start(Start));
+  assert!(!download.is_final());
+
+  let download = download.execute(DownloadMessages::Finish(Finish));
+  assert_eq!(download, Download::complete());
+  assert!(download.is_final());
+  assert!(!download.is_error());
+
+  let download = Download::running(3).execute(DownloadMessages::Cancel(Cancel)).execute(DownloadMessages::Cancel(Cancel));
+  assert!(download.is_final());
+}
+
+#[test]
+fn error_is_not_final() {
+  let download = Download::complete().execute(DownloadMessages::Start(Start));
+  assert!(download.is_error());
+  assert!(!download.is_final());
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Upload {
+    #[initial]
+    Initial { retries: u8 },
+    Sending,
+  }
+);
+
+// the initial state and its constructors are compiled out together
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Sync {
+    #[cfg(any())]
+    #[initial]
+    Pending,
+    Syncing,
+  }
+);
+
+#[test]
+fn initial_state_named_initial() {
+  assert_eq!(Upload::initial(0), Upload::default());
+}
@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Tick<'a> {
+  source: &'a str,
+}
+
+transitions!(Traffic,
+  messages {
+    Advance,
+    /// a car passed the light
+    PassCar { count: u8 },
+    Reset(u8),
+  },
+  [
+    (Green, Advance) => Orange,
+    (Orange, Advance) => Red,
+    (Red, Advance) => Green,
+    (Green, PassCar) => [Green, Orange],
+    (Red, Reset) => Green,
+    (Red, Tick<'a>) => Red
+  ]
+);
+
+impl Green {
+  pub fn on_advance(self, _: Advance) -> Orange {
+    Orange {}
+  }
+
+  pub fn on_pass_car(self, input: PassCar) -> GreenPassCarOutcome {
+    let count = self.count + input.count;
+    if count >= 10 {
+      GreenPassCarOutcome::Orange(Orange {})
+    } else {
+      GreenPassCarOutcome::Green(Green { count })
+    }
+  }
+}
+
+impl Orange {
+  pub fn on_advance(self, _: Advance) -> Red {
+    Red {}
+  }
+}
+
+impl Red {
+  pub fn on_advance(self, _: Advance) -> Green {
+    Green { count: 0 }
+  }
+
+  pub fn on_reset(self, input: Reset) -> Green {
+    Green { count: input.0 }
+  }
+
+  pub fn on_tick(self, _: Tick) -> Red {
+    Red {}
+  }
+}
+
+#[test]
+fn messages() {
+  let t = Traffic::green(0);
+  let t = t.execute(PassCar { count: 5 }.into());
+  assert_eq!(t, Traffic::green(5));
+  let t = t.execute(TrafficMessages::from(Advance));
+  assert_eq!(t, Traffic::orange());
+  let t = t.execute(Advance.into());
+  let t = t.execute(TrafficMessages::Tick(Tick { source: "timer" }));
+  let t = t.execute(Reset(3).into());
+  assert_eq!(t, Traffic::green(3));
+
+  assert_eq!(PassCar { count: 1 }.clone(), PassCar { count: 1 });
+  assert_eq!(format!("{:?}", Reset(2)), "Reset(2)");
+}
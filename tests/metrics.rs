@@ -0,0 +1,102 @@
+#[macro_use]
+extern crate machine;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Order {
+    Placed,
+    Paid { amount: u32 },
+    Shipped,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pay { amount: u32 }
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Ship;
+
+transitions!(Order, metrics,
+  [
+    (Placed, Pay) => Paid,
+    (Paid, Pay) => Paid,
+    (Paid, Ship) => Shipped
+  ]
+);
+
+impl Placed {
+  pub fn on_pay(self, input: Pay) -> Paid {
+    Paid { amount: input.amount }
+  }
+}
+
+impl Paid {
+  pub fn on_pay(self, input: Pay) -> Paid {
+    Paid { amount: self.amount + input.amount }
+  }
+
+  pub fn on_ship(self, _: Ship) -> Shipped {
+    Shipped {}
+  }
+}
+
+#[derive(Default)]
+struct Counters {
+  transitions: HashMap<(OrderKind, OrderMessageKind, OrderKind), usize>,
+  rejected: HashMap<(OrderKind, OrderMessageKind), usize>,
+  dwell: Vec<(OrderKind, Duration)>,
+}
+
+impl OrderMetricsSink for Counters {
+  fn transition(&mut self, from: OrderKind, message: OrderMessageKind, to: OrderKind) {
+    *self.transitions.entry((from, message, to)).or_insert(0) += 1;
+  }
+
+  fn rejected(&mut self, from: OrderKind, message: OrderMessageKind) {
+    *self.rejected.entry((from, message)).or_insert(0) += 1;
+  }
+
+  fn dwell(&mut self, state: OrderKind, duration: Duration) {
+    self.dwell.push((state, duration));
+  }
+}
+
+#[test]
+fn metrics() {
+  let start = Instant::now();
+  let now = Cell::new(start);
+  let clock = || now.get();
+
+  let mut order = OrderInstrumented::new(Order::placed(), Counters::default(), &clock);
+
+  now.set(start + Duration::from_secs(2));
+  order.execute(OrderMessages::Pay(Pay { amount: 10 }));
+  now.set(start + Duration::from_secs(3));
+  order.execute(OrderMessages::Pay(Pay { amount: 5 }));
+  now.set(start + Duration::from_secs(10));
+  order.execute(OrderMessages::Ship(Ship));
+  now.set(start + Duration::from_secs(11));
+  order.execute(OrderMessages::Ship(Ship));
+  now.set(start + Duration::from_secs(12));
+  order.execute(OrderMessages::Ship(Ship));
+
+  assert_eq!(order.machine(), &Order::error());
+
+  let counters = order.sink();
+  assert_eq!(counters.transitions.get(&(OrderKind::Placed, OrderMessageKind::Pay, OrderKind::Paid)), Some(&1));
+  assert_eq!(counters.transitions.get(&(OrderKind::Paid, OrderMessageKind::Pay, OrderKind::Paid)), Some(&1));
+  assert_eq!(counters.transitions.get(&(OrderKind::Paid, OrderMessageKind::Ship, OrderKind::Shipped)), Some(&1));
+  assert_eq!(counters.rejected.get(&(OrderKind::Shipped, OrderMessageKind::Ship)), Some(&1));
+  assert_eq!(counters.rejected.get(&(OrderKind::Error, OrderMessageKind::Ship)), Some(&1));
+
+  assert_eq!(counters.dwell, vec![
+    (OrderKind::Placed, Duration::from_secs(2)),
+    (OrderKind::Paid, Duration::from_secs(1)),
+    (OrderKind::Paid, Duration::from_secs(7)),
+    (OrderKind::Shipped, Duration::from_secs(1)),
+  ]);
+}
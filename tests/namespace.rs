@@ -0,0 +1,86 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[machine(namespace)]
+  enum Traffic {
+    Idle,
+    Green,
+    Red { cars: u8 },
+  }
+);
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  #[machine(namespace)]
+  enum Pump {
+    Idle,
+    Running(u8),
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Start;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Stop;
+
+transitions!(Traffic, namespace, traits,
+  [
+    (Idle, Start) => [Green, Idle],
+    (Green, Stop) => [Idle, Red],
+    (Red, Start) => Green |_state, _input| traffic::Green
+  ]
+);
+
+// the same state names, traits and outcome enums as `Traffic`
+transitions!(Pump, inherit, traits,
+  [
+    (Idle, Start) => [Running, Idle],
+    (Running, Stop) => Idle
+  ]
+);
+
+methods!(Pump, inherit,
+  [
+    Running => get 0 as speed: u8
+  ]
+);
+
+impl TrafficIdleTransitions for traffic::Idle {
+  fn on_start(self, _: Start) -> TrafficIdleStartOutcome {
+    TrafficIdleStartOutcome::Green(traffic::Green)
+  }
+}
+
+impl TrafficGreenTransitions for traffic::Green {
+  fn on_stop(self, _: Stop) -> TrafficGreenStopOutcome {
+    TrafficGreenStopOutcome::Red(traffic::Red { cars: 0 })
+  }
+}
+
+impl PumpIdleTransitions for pump::Idle {
+  fn on_start(self, _: Start) -> PumpIdleStartOutcome {
+    PumpIdleStartOutcome::Running(pump::Running(3))
+  }
+}
+
+impl PumpRunningTransitions for pump::Running {
+  fn on_stop(self, _: Stop) -> pump::Idle {
+    pump::Idle
+  }
+}
+
+#[test]
+fn namespaced_states() {
+  let traffic = Traffic::idle().execute(TrafficMessages::Start(Start));
+  assert_eq!(traffic, Traffic::Green(traffic::Green));
+  let traffic = traffic.execute(TrafficMessages::Stop(Stop));
+  assert_eq!(traffic, Traffic::red(0));
+  assert_eq!(traffic.execute(TrafficMessages::Start(Start)), Traffic::green());
+
+  let pump = Pump::idle().execute(PumpMessages::Start(Start));
+  assert_eq!(pump.speed(), Some(&3));
+  assert_eq!(pump.execute(PumpMessages::Stop(Stop)), Pump::Idle(pump::Idle));
+}
@@ -0,0 +1,44 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Parcel {
+    Sorting,
+    Shipped { carrier: String },
+    Returned,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Scan { pub carrier: Option<String> }
+
+transitions!(Parcel,
+  [
+    (Sorting, Scan) => [Shipped, Returned]
+  ]
+);
+
+impl Sorting {
+  pub fn on_scan(self, input: Scan) -> SortingScanOutcome {
+    match input.carrier {
+      Some(carrier) => SortingScanOutcome::Shipped(Shipped { carrier }),
+      None => SortingScanOutcome::Returned(Returned {}),
+    }
+  }
+}
+
+#[test]
+fn outcome_enum() {
+  let parcel = Parcel::sorting().on_scan(Scan { carrier: Some("post".to_string()) });
+  assert_eq!(parcel, Parcel::shipped("post".to_string()));
+
+  let parcel = Parcel::sorting().execute(ParcelMessages::Scan(Scan { carrier: None }));
+  assert_eq!(parcel, Parcel::returned());
+}
+
+#[test]
+fn outcome_into_machine() {
+  let outcome = SortingScanOutcome::Returned(Returned {});
+  assert_eq!(Parcel::from(outcome), Parcel::returned());
+}
@@ -0,0 +1,72 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Link {
+    Idle,
+    Waiting { seq: u32 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Send {
+  seq: u32,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Ack {
+  seq: u32,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Command {
+  Transmit(u32),
+  StartTimer,
+  StopTimer,
+}
+
+transitions!(Link, output = Command,
+  [
+    (Idle, Send) => Waiting,
+    (Waiting, Ack) => [Idle, Waiting]
+  ]
+);
+
+impl Idle {
+  pub fn on_send(self, input: Send, output: &mut Vec<Command>) -> Waiting {
+    output.push(Command::Transmit(input.seq));
+    output.push(Command::StartTimer);
+    Waiting { seq: input.seq }
+  }
+}
+
+impl Waiting {
+  pub fn on_ack(self, input: Ack, output: &mut Vec<Command>) -> WaitingAckOutcome {
+    if input.seq == self.seq {
+      output.push(Command::StopTimer);
+      WaitingAckOutcome::Idle(Idle {})
+    } else {
+      WaitingAckOutcome::Waiting(self)
+    }
+  }
+}
+
+#[test]
+fn output() {
+  let (link, commands) = Link::idle().execute(LinkMessages::Send(Send { seq: 1 }));
+  assert_eq!(link, Link::waiting(1));
+  assert_eq!(commands, vec![Command::Transmit(1), Command::StartTimer]);
+
+  let (link, commands) = link.on_ack(Ack { seq: 0 });
+  assert_eq!(link, Link::waiting(1));
+  assert_eq!(commands, vec![]);
+
+  let (link, commands) = link.execute(LinkMessages::Ack(Ack { seq: 1 }));
+  assert_eq!(link, Link::idle());
+  assert_eq!(commands, vec![Command::StopTimer]);
+
+  let (link, commands) = link.on_ack(Ack { seq: 1 });
+  assert_eq!(link, Link::error());
+  assert_eq!(commands, vec![]);
+}
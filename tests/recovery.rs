@@ -0,0 +1,88 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::{Duration, Instant};
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Worker {
+    Initial,
+    Busy { jobs: u8 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Job;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Reset { jobs: u8 }
+
+transitions!(Worker,
+  [
+    (Initial, Job) => Busy,
+    (Busy, Job) => [Busy, Error],
+    (Error, Reset) => [Initial, Busy, Error],
+    (Error, after(10s)) => Initial
+  ]
+);
+
+impl Initial {
+  pub fn on_job(self, _: Job) -> Busy {
+    Busy { jobs: 1 }
+  }
+}
+
+impl Busy {
+  pub fn on_job(self, _: Job) -> BusyJobOutcome {
+    if self.jobs < 2 {
+      BusyJobOutcome::Busy(Busy { jobs: self.jobs + 1 })
+    } else {
+      BusyJobOutcome::Error(WorkerErrorState)
+    }
+  }
+}
+
+impl WorkerErrorState {
+  pub fn on_reset(self, input: Reset) -> ErrorResetOutcome {
+    match input.jobs {
+      0 => ErrorResetOutcome::Initial(Initial {}),
+      255 => ErrorResetOutcome::Error(self),
+      jobs => ErrorResetOutcome::Busy(Busy { jobs }),
+    }
+  }
+
+  pub fn on_timeout(self) -> Initial {
+    Initial {}
+  }
+}
+
+#[test]
+fn recover() {
+  let worker = Worker::initial()
+    .execute(WorkerMessages::Job(Job))
+    .execute(WorkerMessages::Job(Job))
+    .execute(WorkerMessages::Job(Job));
+  assert_eq!(worker, Worker::error());
+
+  // the error state keeps rejecting other messages
+  let worker = worker.execute(WorkerMessages::Job(Job));
+  assert_eq!(worker, Worker::error());
+  assert!(worker.can_handle(&WorkerMessages::Reset(Reset { jobs: 0 })));
+  assert_eq!(worker.accepted_messages(), &[WorkerMessageKind::Reset]);
+
+  assert_eq!(worker.clone().execute(WorkerMessages::Reset(Reset { jobs: 255 })), Worker::error());
+  assert_eq!(worker.clone().execute(WorkerMessages::Reset(Reset { jobs: 1 })), Worker::busy(1));
+  assert_eq!(worker.execute(WorkerMessages::Reset(Reset { jobs: 0 })), Worker::initial());
+}
+
+#[test]
+fn recover_after_timeout() {
+  let start = Instant::now();
+  let clock = WorkerManualClock::new(start);
+  let mut worker = WorkerTimers::new(Worker::error(), clock.clone());
+
+  assert_eq!(worker.next_deadline(), Some(start + Duration::from_secs(10)));
+  clock.advance(Duration::from_secs(10));
+  assert!(worker.poll_timeouts(clock.now()));
+  assert_eq!(worker.into_inner(), Worker::initial());
+}
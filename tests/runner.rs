@@ -0,0 +1,104 @@
+#[macro_use]
+extern crate machine;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Request {
+    Headers { count: u8 },
+    Ready,
+    Body { received: usize },
+    Done,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Header;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct HeaderEnd;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct StartBody;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Data {
+  len: usize,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct End;
+
+transitions!(Request, runner,
+  [
+    (Headers, Header) => Headers,
+    (Headers, HeaderEnd) => Ready,
+    (Ready, StartBody) => Body,
+    (Body, Data) => Body,
+    (Body, End) => Done
+  ]
+);
+
+impl Headers {
+  pub fn on_header(self, _: Header, _: &mut RequestQueue) -> Headers {
+    Headers { count: self.count + 1 }
+  }
+
+  pub fn on_header_end(self, _: HeaderEnd, queue: &mut RequestQueue) -> Ready {
+    // a request without headers ends before its body, which is rejected
+    if self.count == 0 {
+      queue.push(RequestMessages::End(End));
+    }
+    queue.push(RequestMessages::StartBody(StartBody));
+    Ready {}
+  }
+}
+
+impl Ready {
+  pub fn on_start_body(self, _: StartBody, _: &mut RequestQueue) -> Body {
+    Body { received: 0 }
+  }
+}
+
+impl Body {
+  pub fn on_data(self, input: Data, _: &mut RequestQueue) -> Body {
+    Body { received: self.received + input.len }
+  }
+
+  pub fn on_end(self, _: End, _: &mut RequestQueue) -> Done {
+    Done {}
+  }
+}
+
+#[test]
+fn run_to_completion() {
+  let mut runner = RequestRunner::new(Request::headers(0));
+
+  assert_eq!(runner.send(RequestMessages::Header(Header)), Ok(()));
+  assert_eq!(runner.send(RequestMessages::HeaderEnd(HeaderEnd)), Ok(()));
+  assert_eq!(runner.machine(), &Request::body(0));
+
+  assert_eq!(runner.send(RequestMessages::Data(Data { len: 5 })), Ok(()));
+  assert_eq!(runner.send(RequestMessages::End(End)), Ok(()));
+  assert_eq!(runner.machine(), &Request::done());
+
+  let error = runner.send(RequestMessages::Data(Data { len: 1 })).unwrap_err();
+  assert_eq!(error, RequestRunError { from: RequestKind::Done, message: RequestMessageKind::Data });
+  assert_eq!(error.to_string(), "Request: Done rejected Data");
+  assert_eq!(runner.into_inner(), Request::error());
+}
+
+#[test]
+fn raised_error() {
+  let mut runner = RequestRunner::new(Request::headers(0));
+
+  assert_eq!(
+    runner.send(RequestMessages::HeaderEnd(HeaderEnd)),
+    Err(RequestRunError { from: RequestKind::Ready, message: RequestMessageKind::End })
+  );
+
+  // the StartBody raised after End was dropped
+  let mut queue = RequestQueue::new();
+  let machine = runner.into_inner().execute(RequestMessages::Header(Header), &mut queue);
+  assert_eq!(machine, Request::error());
+  assert!(queue.is_empty());
+}
@@ -0,0 +1,76 @@
+#![cfg(feature = "runtime-log")]
+
+#[macro_use]
+extern crate machine;
+extern crate log;
+
+use std::sync::Mutex;
+use log::{Level, Log, Metadata, Record};
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Door {
+    Open,
+    Closed,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Push;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pull;
+
+transitions!(Door,
+  [
+    (Open, Push) => Closed,
+    (Closed, Pull) => Open
+  ]
+);
+
+impl Open {
+  pub fn on_push(self, _: Push) -> Closed {
+    Closed {}
+  }
+}
+
+impl Closed {
+  pub fn on_pull(self, _: Pull) -> Open {
+    Open {}
+  }
+}
+
+struct Capture {
+  records: Mutex<Vec<(Level, String)>>,
+}
+
+impl Log for Capture {
+  fn enabled(&self, _: &Metadata) -> bool {
+    true
+  }
+
+  fn log(&self, record: &Record) {
+    self.records.lock().unwrap().push((record.level(), record.args().to_string()));
+  }
+
+  fn flush(&self) {}
+}
+
+static LOGGER: Capture = Capture { records: Mutex::new(Vec::new()) };
+
+#[test]
+fn records() {
+  log::set_logger(&LOGGER).unwrap();
+  log::set_max_level(log::LevelFilter::Trace);
+
+  let door = Door::open();
+  let door = door.execute(DoorMessages::Push(Push));
+  let door = door.on_push(Push);
+  assert_eq!(door, Door::error());
+
+  assert_eq!(*LOGGER.records.lock().unwrap(), vec![
+    (Level::Trace, "Door: Open received Push".to_string()),
+    (Level::Debug, "Door: Open -> Closed on Push".to_string()),
+    (Level::Warn, "Door: Closed rejected Push, moving to Error".to_string()),
+  ]);
+}
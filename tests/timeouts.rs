@@ -0,0 +1,139 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::{Duration, Instant};
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Connection {
+    Connecting { attempts: u8 },
+    Connected,
+    Idle,
+    Failed,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Established;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Ping;
+
+transitions!(Connection,
+  [
+    (Connecting, Established) => Connected,
+    (Connecting, after(5s)) => [Connecting, Failed],
+    (Connected, Ping) => Connected,
+    (Connected, after(30s)) => Idle,
+    (Idle, after(Duration::from_secs(60))) => Failed,
+  ]
+);
+
+impl Connecting {
+  pub fn on_established(self, _: Established) -> Connected {
+    Connected {}
+  }
+
+  pub fn on_timeout(self) -> ConnectingTimeoutOutcome {
+    if self.attempts < 2 {
+      ConnectingTimeoutOutcome::Connecting(Connecting { attempts: self.attempts + 1 })
+    } else {
+      ConnectingTimeoutOutcome::Failed(Failed {})
+    }
+  }
+}
+
+impl Connected {
+  pub fn on_ping(self, _: Ping) -> Connected {
+    Connected {}
+  }
+
+  pub fn on_timeout(self) -> Idle {
+    Idle {}
+  }
+}
+
+impl Idle {
+  pub fn on_timeout(self) -> Failed {
+    Failed {}
+  }
+}
+
+#[test]
+fn timeout() {
+  assert_eq!(Connection::connecting(0).timeout(), Some(Duration::from_secs(5)));
+  assert_eq!(Connection::connected().timeout(), Some(Duration::from_secs(30)));
+  assert_eq!(Connection::failed().timeout(), None);
+
+  assert_eq!(Connection::connecting(2).on_timeout(), Connection::failed());
+  assert_eq!(Connection::connected().on_timeout(), Connection::idle());
+  assert_eq!(Connection::failed().on_timeout(), Connection::error());
+}
+
+#[test]
+fn timers() {
+  let start = Instant::now();
+  let clock = ConnectionManualClock::new(start);
+  let mut connection = ConnectionTimers::new(Connection::connecting(0), clock.clone());
+
+  assert_eq!(connection.next_deadline(), Some(start + Duration::from_secs(5)));
+  clock.advance(Duration::from_secs(4));
+  assert!(!connection.poll_timeouts(clock.now()));
+
+  clock.advance(Duration::from_secs(1));
+  assert!(connection.poll_timeouts(clock.now()));
+  assert_eq!(connection.machine(), &Connection::connecting(1));
+  assert_eq!(connection.next_deadline(), Some(start + Duration::from_secs(10)));
+
+  clock.advance(Duration::from_secs(2));
+  connection.execute(ConnectionMessages::Established(Established));
+  assert_eq!(connection.machine(), &Connection::connected());
+
+  // a message accepted without changing state restarts the timer
+  clock.advance(Duration::from_secs(20));
+  connection.execute(ConnectionMessages::Ping(Ping));
+  assert_eq!(connection.next_deadline(), Some(start + Duration::from_secs(57)));
+
+  // expired timeouts are chained from their deadlines
+  assert!(connection.poll_timeouts(start + Duration::from_secs(120)));
+  assert_eq!(connection.into_inner(), Connection::failed());
+}
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Poller {
+    Polling { count: u8 },
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Reset;
+
+transitions!(Poller,
+  [
+    (Polling, Reset) => Polling,
+    (Polling, after(0ms)) => Polling
+  ]
+);
+
+impl Polling {
+  pub fn on_reset(self, _: Reset) -> Polling {
+    Polling { count: 0 }
+  }
+
+  pub fn on_timeout(self) -> Polling {
+    Polling { count: self.count + 1 }
+  }
+}
+
+#[test]
+fn zero_timeout() {
+  let start = Instant::now();
+  let clock = PollerManualClock::new(start);
+  let mut poller = PollerTimers::new(Poller::polling(0), clock.clone());
+
+  // the deadline does not move, so each poll fires the timeout once
+  assert!(poller.poll_timeouts(clock.now()));
+  assert!(poller.poll_timeouts(clock.now()));
+  assert_eq!(poller.into_inner(), Poller::polling(2));
+}
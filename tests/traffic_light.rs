@@ -135,3 +135,95 @@ fn test() {
   t = t.on_advance(Advance);
   assert_eq!(t, TrafficLight::error());
 }
+
+#[test]
+fn kind() {
+  use std::collections::HashMap;
+  use prefix::Advance;
+
+  let t = TrafficLight::green(0);
+  assert_eq!(t.kind(), TrafficLightKind::Green);
+  assert_eq!(TrafficLight::error().kind(), TrafficLightKind::Error);
+  assert_eq!(t.kind().name(), "Green");
+  assert_eq!(TrafficLightKind::BlinkingOrange.to_string(), "BlinkingOrange");
+  assert_eq!("Orange".parse(), Ok(TrafficLightKind::Orange));
+  assert!("Purple".parse::<TrafficLightKind>().is_err());
+
+  let mut seen = HashMap::new();
+  *seen.entry(t.kind()).or_insert(0) += 1;
+  assert_eq!(seen.get(&TrafficLightKind::Green), Some(&1));
+
+  let msg: TrafficLightMessages<String> = TrafficLightMessages::Advance(Advance);
+  assert_eq!(msg.kind(), TrafficLightMessageKind::Advance);
+  assert_eq!(TrafficLightMessageKind::PassCar.name(), "PassCar");
+  assert_eq!("Toggle".parse(), Ok(TrafficLightMessageKind::Toggle));
+}
+
+#[test]
+fn queries() {
+  use prefix::Advance;
+
+  let name = "test".to_string();
+  let pass: TrafficLightMessages<String> = TrafficLightMessages::PassCar(PassCar { count: 1, name: &name });
+  let advance: TrafficLightMessages<String> = TrafficLightMessages::Advance(Advance);
+
+  let t = TrafficLight::green(0);
+  assert!(t.can_handle(&pass));
+  assert_eq!(t.accepted_messages(), &[
+    TrafficLightMessageKind::Advance,
+    TrafficLightMessageKind::PassCar,
+    TrafficLightMessageKind::Toggle,
+  ]);
+  assert_eq!(t.next_state_kind(&advance), Some(TrafficLightKind::Orange));
+  assert_eq!(t.next_state_kind(&pass), None);
+
+  let t = TrafficLight::blinking_orange();
+  assert!(!t.can_handle(&advance));
+  assert_eq!(t.accepted_messages(), &[TrafficLightMessageKind::Toggle]);
+  assert_eq!(t.next_state_kind(&advance), Some(TrafficLightKind::Error));
+
+  let t = TrafficLight::error();
+  assert!(!t.can_handle(&advance));
+  assert!(t.accepted_messages().is_empty());
+}
+
+#[derive(Default)]
+struct Recorder {
+  transitions: Vec<(TrafficLightKind, TrafficLightMessageKind, TrafficLightKind)>,
+  rejected: Vec<(TrafficLightKind, TrafficLightMessageKind)>,
+}
+
+impl<'a, T> TrafficLightObserver<'a, T> for Recorder {
+  fn on_transition(&mut self, from: TrafficLightKind, message: &TrafficLightMessages<'a, T>, to: TrafficLightKind) {
+    self.transitions.push((from, message.kind(), to));
+  }
+
+  fn on_rejected(&mut self, from: TrafficLightKind, message: &TrafficLightMessages<'a, T>) {
+    self.rejected.push((from, message.kind()));
+  }
+}
+
+#[test]
+fn observer() {
+  use prefix::Advance;
+
+  let name = "test".to_string();
+  let mut recorder = Recorder::default();
+
+  let pass = TrafficLightMessages::PassCar(PassCar { count: 1, name: &name });
+  let advance: TrafficLightMessages<String> = TrafficLightMessages::Advance(Advance);
+
+  let mut t = TrafficLight::green(9);
+  t = t.execute_with(&mut recorder, pass.clone());
+  t = t.execute_with(&mut recorder, advance);
+  t = t.execute_with(&mut recorder, pass);
+  assert_eq!(t, TrafficLight::error());
+
+  assert_eq!(recorder.transitions, vec![
+    (TrafficLightKind::Green, TrafficLightMessageKind::PassCar, TrafficLightKind::Orange),
+    (TrafficLightKind::Orange, TrafficLightMessageKind::Advance, TrafficLightKind::Red),
+  ]);
+  assert_eq!(recorder.rejected, vec![
+    (TrafficLightKind::Red, TrafficLightMessageKind::PassCar),
+  ]);
+}
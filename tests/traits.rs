@@ -0,0 +1,72 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::Duration;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Advance;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct PassCar<'a> { count: u8, name: &'a str }
+
+transitions!(Traffic, traits,
+  [
+    (Green, Advance) => Orange,
+    (Orange, Advance) => Red |_, _| Red {},
+    (Red, Advance) => Green,
+    (Green, PassCar<'a>) => [Green, Orange],
+    (Red, after(30s)) => Green
+  ]
+);
+
+impl GreenTransitions for Green {
+  fn on_advance(self, _: Advance) -> Orange {
+    Orange {}
+  }
+
+  fn on_pass_car<'a>(self, input: PassCar<'a>) -> GreenPassCarOutcome {
+    let count = self.count + input.count;
+    if count >= 10 {
+      GreenPassCarOutcome::Orange(Orange {})
+    } else {
+      GreenPassCarOutcome::Green(Green { count })
+    }
+  }
+}
+
+impl RedTransitions for Red {
+  fn on_advance(self, _: Advance) -> Green {
+    Green { count: 0 }
+  }
+
+  fn on_timeout(self) -> Green {
+    Green { count: 0 }
+  }
+}
+
+#[test]
+fn traits() {
+  let t = Traffic::green(0);
+  let t = t.on_pass_car(PassCar { count: 5, name: "a" });
+  assert_eq!(t, Traffic::green(5));
+  let t = t.execute(TrafficMessages::PassCar(PassCar { count: 7, name: "b" }));
+  assert_eq!(t, Traffic::orange());
+  let t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::red());
+  assert_eq!(t.timeout(), Some(Duration::from_secs(30)));
+  let t = t.on_timeout();
+  assert_eq!(t, Traffic::green(0));
+  let t = t.on_advance(Advance);
+  assert_eq!(t, Traffic::orange());
+
+  assert_eq!(RedTransitions::on_advance(Red {}, Advance), Green { count: 0 });
+}
@@ -0,0 +1,72 @@
+#[macro_use]
+extern crate machine;
+
+use std::time::Duration;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Job {
+    Queued,
+    Running { worker: u8 },
+    Retrying(u32, Duration),
+    Finished,
+  }
+);
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Fail;
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Retry { worker: u8 }
+
+transitions!(Job,
+  [
+    (Running, Fail) => Retrying,
+    (Retrying, Fail) => Retrying,
+    (Retrying, Retry) => Running
+  ]
+);
+
+methods!(Job,
+  [
+    Retrying => get 0 as attempts: u32,
+    Retrying => set 1 as delay: Duration,
+    Running => get worker: u8
+  ]
+);
+
+impl Running {
+  pub fn on_fail(self, _: Fail) -> Retrying {
+    Retrying(1, Duration::from_secs(1))
+  }
+}
+
+impl Retrying {
+  pub fn on_fail(self, _: Fail) -> Retrying {
+    Retrying(self.0 + 1, self.1 * 2)
+  }
+
+  pub fn on_retry(self, input: Retry) -> Running {
+    Running { worker: input.worker }
+  }
+}
+
+#[test]
+fn tuple_states() {
+  assert_eq!(Job::retrying(2, Duration::from_secs(4)), Job::Retrying(Retrying(2, Duration::from_secs(4))));
+  assert_eq!(Job::queued(), Job::Queued(Queued));
+  assert_eq!(Job::finished(), Job::Finished(Finished));
+
+  let job = Job::running(3).execute(JobMessages::Fail(Fail)).execute(JobMessages::Fail(Fail));
+  assert_eq!(job, Job::retrying(2, Duration::from_secs(2)));
+  assert_eq!(job.attempts(), Some(&2));
+  assert_eq!(job.worker(), None);
+}
+
+#[test]
+fn positional_setter() {
+  let mut job = Job::retrying(1, Duration::from_secs(1));
+  *job.delay_mut().unwrap() = Duration::from_secs(10);
+  assert_eq!(job, Job::retrying(1, Duration::from_secs(10)));
+  assert_eq!(job.execute(JobMessages::Retry(Retry { worker: 4 })).worker(), Some(&4));
+}
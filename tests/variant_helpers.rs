@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate machine;
+
+use std::convert::TryFrom;
+
+machine!(
+  #[derive(Clone,Debug,PartialEq)]
+  enum Traffic {
+    Green { count: u8 },
+    Orange,
+    Red(u32),
+    #[cfg(any())]
+    Blinking,
+  }
+);
+
+#[test]
+fn inspect() {
+  let traffic = Traffic::green(2);
+  assert!(traffic.is_green());
+  assert!(!traffic.is_orange());
+  assert!(!traffic.is_error());
+
+  assert_eq!(traffic.as_green(), Some(&Green { count: 2 }));
+  assert_eq!(traffic.as_red(), None);
+  assert_eq!(Traffic::error().as_green(), None);
+}
+
+#[test]
+fn modify() {
+  let mut traffic = Traffic::green(2);
+  traffic.as_green_mut().unwrap().count += 1;
+  assert_eq!(traffic, Traffic::green(3));
+
+  if let Some(red) = Traffic::red(10).as_red_mut() {
+    red.0 = 20;
+  }
+  assert!(traffic.as_orange_mut().is_none());
+}
+
+#[test]
+fn extract() {
+  assert_eq!(Traffic::red(10).into_red(), Ok(Red(10)));
+  assert_eq!(Traffic::orange().into_red(), Err(Traffic::orange()));
+  assert_eq!(Traffic::error().into_orange(), Err(Traffic::error()));
+}
+
+#[test]
+fn conversions() {
+  let traffic: Traffic = Green { count: 1 }.into();
+  assert_eq!(traffic, Traffic::green(1));
+  assert_eq!(Traffic::from(Orange), Traffic::orange());
+
+  assert_eq!(Green::try_from(traffic.clone()), Ok(Green { count: 1 }));
+  assert_eq!(Red::try_from(traffic.clone()), Err(traffic));
+}
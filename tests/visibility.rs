@@ -0,0 +1,97 @@
+#[macro_use]
+extern crate machine;
+
+mod protocol {
+  #[derive(Clone,Debug,PartialEq)]
+  pub(crate) struct Token(pub(crate) u8);
+
+  #[derive(Clone,Debug,PartialEq)]
+  pub(crate) struct Login(pub(crate) u8);
+
+  machine!(
+    #[derive(Clone,Debug,PartialEq)]
+    pub(crate) enum Handshake {
+      Waiting,
+      Authenticated { token: Token },
+    }
+  );
+
+  transitions!(Handshake, inherit,
+    [
+      (Waiting, Login) => Authenticated
+    ]
+  );
+
+  methods!(Handshake, inherit,
+    [
+      Authenticated => get token: Token,
+      Authenticated => pub(self) set token: Token
+    ]
+  );
+
+  impl Waiting {
+    pub(crate) fn on_login(self, input: Login) -> Authenticated {
+      Authenticated { token: Token(input.0) }
+    }
+  }
+
+  pub(crate) fn refresh(handshake: &mut Handshake) {
+    if let Some(token) = handshake.token_mut() {
+      token.0 += 1;
+    }
+  }
+}
+
+// without `inherit`, the generated items are public and can be declared in
+// another module, with a path to the macro
+mod states {
+  machine!(
+    #[derive(Clone,Debug,PartialEq)]
+    pub(crate) enum Session {
+      Closed,
+      Open { pub(crate) id: u8 },
+    }
+  );
+}
+
+mod handlers {
+  use super::states::*;
+
+  #[derive(Clone,Debug,PartialEq)]
+  pub struct Connect;
+
+  machine::transitions!(Session,
+    [
+      (Closed, Connect) => Open
+    ]
+  );
+
+  machine::methods!(Session,
+    [
+      Open => get id: u8
+    ]
+  );
+
+  impl Closed {
+    pub fn on_connect(self, _: Connect) -> Open {
+      Open { id: 1 }
+    }
+  }
+}
+
+use protocol::*;
+
+#[test]
+fn crate_visible() {
+  let mut handshake = Handshake::waiting().execute(HandshakeMessages::Login(Login(4)));
+  assert_eq!(handshake.kind(), HandshakeKind::Authenticated);
+
+  refresh(&mut handshake);
+  assert_eq!(handshake.token(), Some(&Token(5)));
+}
+
+#[test]
+fn other_module() {
+  let session = states::Session::closed().execute(handlers::SessionMessages::Connect(handlers::Connect));
+  assert_eq!(session.id(), Some(&1));
+}